@@ -0,0 +1,81 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::node::{Node, Value};
+use crate::settings::Mode;
+use crate::vm::{Program, Frame};
+
+pub struct Report {
+    pub rows: usize,
+    pub tree: Duration,
+    pub vm: Duration
+}
+
+/// Evaluate `node` over `rows` generated data rows, once with the tree
+/// walker and once with the compiled program, and time both. Both must
+/// agree on every row before either is timed.
+pub fn compare(node: &Node, rows: usize, mode: Mode) -> Result<Report, String> {
+    let prog = Program::compile(node, mode)?;
+    let slots = prog.slots();
+
+    let data = (0..rows)
+        .map(|row| (0..slots.len())
+            .map(|i| Value::Num(((row + i) % 97 + 1) as i64))
+            .collect::<Vec<_>>()
+        )
+        .collect::<Vec<_>>();
+
+    let envs = data
+        .iter()
+        .map(|row| {
+            let mut env = Env::new();
+            env.settings.mode = mode;
+            slots
+                .iter()
+                .zip(row)
+                .for_each(|(name, val)| env.bind(name, val.clone()));
+            env
+        })
+        .collect::<Vec<_>>();
+
+    let mut frame = Frame::new();
+    for (index, (env, row)) in envs.iter().zip(&data).enumerate() {
+        let tree = outcome(node.eval(env));
+        let vm = outcome(prog.eval(&mut frame, row));
+        if tree != vm {
+            return Err(format!("row {}: tree gives {}, vm gives {}", index + 1, tree, vm))
+        }
+    }
+
+    let start = Instant::now();
+    for env in &envs {
+        let _ = black_box(node.eval(env));
+    }
+    let tree = start.elapsed();
+
+    let start = Instant::now();
+    for row in &data {
+        let _ = black_box(prog.eval(&mut frame, row));
+    }
+    let vm = start.elapsed();
+
+    Ok(Report { rows, tree, vm })
+}
+
+fn outcome(res: Result<Value, EvalError>) -> String {
+    match res {
+        Ok(val) => val.to_string(),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let rows = self.rows.max(1) as u32;
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "tree: {:?} ({:?}/row)", self.tree, self.tree / rows)?;
+        write!(f, "vm:   {:?} ({:?}/row)", self.vm, self.vm / rows)
+    }
+}
@@ -0,0 +1,461 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::complex::Complex;
+use crate::eval::{EvalError, MAX_ITERATIONS};
+use crate::matrix::Matrix;
+use crate::node::{Value, BinaryOp};
+use crate::range::Range;
+use crate::settings::Mode;
+
+use self::Func::{Plain, Higher};
+
+/// Calls a function value with arguments, for builtins such as `map`.
+pub type Apply<'a> = dyn Fn(&Value, Vec<Value>) -> Result<Value, EvalError> + 'a;
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    func: Func
+}
+
+enum Func {
+    Plain(fn(&[Value]) -> Result<Value, EvalError>),
+    /// Takes function values, and calls them through the evaluator.
+    Higher(fn(&[Value], &Apply) -> Result<Value, EvalError>),
+}
+
+static BUILTINS: &'static [Builtin] = &[
+    Builtin { name: "abs", params: &["x"],      func: Plain(abs) },
+    Builtin { name: "min", params: &["a", "b"], func: Plain(min) },
+    Builtin { name: "max", params: &["a", "b"], func: Plain(max) },
+    Builtin { name: "gcd", params: &["a", "b"], func: Plain(gcd) },
+
+    Builtin { name: "sqrt",  params: &["x"], func: Plain(sqrt) },
+    Builtin { name: "floor", params: &["x"], func: Plain(floor) },
+    Builtin { name: "ceil",  params: &["x"], func: Plain(ceil) },
+    Builtin { name: "round", params: &["x"], func: Plain(round) },
+
+    Builtin { name: "re",   params: &["z"], func: Plain(re) },
+    Builtin { name: "im",   params: &["z"], func: Plain(im) },
+    Builtin { name: "arg",  params: &["z"], func: Plain(arg) },
+    Builtin { name: "conj", params: &["z"], func: Plain(conj) },
+
+    Builtin { name: "sum",   params: &["xs"], func: Plain(fold_sum) },
+    Builtin { name: "prod",  params: &["xs"], func: Plain(fold_prod) },
+    Builtin { name: "count", params: &["xs"], func: Plain(fold_count) },
+    Builtin { name: "any",   params: &["xs"], func: Plain(fold_any) },
+    Builtin { name: "all",   params: &["xs"], func: Plain(fold_all) },
+
+    Builtin { name: "step", params: &["range", "n"], func: Plain(step) },
+    Builtin { name: "len",  params: &["xs"],         func: Plain(len) },
+
+    Builtin { name: "map",     params: &["xs", "f"],  func: Higher(map) },
+    Builtin { name: "filter",  params: &["xs", "f"],  func: Higher(filter) },
+    Builtin { name: "reduce",  params: &["xs", "f"],  func: Higher(reduce) },
+    Builtin { name: "sort_by", params: &["xs", "f"],  func: Higher(sort_by) },
+    Builtin { name: "zip",     params: &["xs", "ys"], func: Plain(zip) },
+    Builtin { name: "sort",    params: &["xs"],       func: Plain(sort) },
+    Builtin { name: "unique",  params: &["xs"],       func: Plain(unique) },
+    Builtin { name: "reverse", params: &["xs"],       func: Plain(reverse) },
+
+    Builtin { name: "shape",     params: &["m"],      func: Plain(shape) },
+    Builtin { name: "transpose", params: &["m"],      func: Plain(transpose) },
+    Builtin { name: "det",       params: &["m"],      func: Plain(det) },
+    Builtin { name: "inverse",   params: &["m"],      func: Plain(inverse) },
+    Builtin { name: "identity",  params: &["n"],      func: Plain(identity) },
+    Builtin { name: "solve",     params: &["a", "b"], func: Plain(solve) },
+];
+
+/// How a fold reduces a sequence to one value. The evaluator feeds folds
+/// item by item, so `sum(i ** 2 for i in 1..=100)` builds no sequence.
+pub struct Fold {
+    pub name: &'static str,
+    init: i64,
+    func: fn(Value, Value) -> Result<Value, EvalError>
+}
+
+static FOLDS: &'static [Fold] = &[
+    Fold { name: "sum",   init: 0, func: |acc, x| BinaryOp::Add.apply(acc, x) },
+    Fold { name: "prod",  init: 1, func: |acc, x| BinaryOp::Mul.apply(acc, x) },
+    Fold { name: "count", init: 0, func: |acc, x| BinaryOp::Add.apply(acc, Value::Num(x.truthy()? as i64)) },
+    Fold { name: "any",   init: 0, func: |acc, x| BinaryOp::LogicOr.apply(acc, x) },
+    Fold { name: "all",   init: 1, func: |acc, x| BinaryOp::LogicAnd.apply(acc, x) },
+];
+
+/// Builtins that complex mode uses in place of those of the same name.
+static COMPLEX: &'static [Builtin] = &[
+    Builtin { name: "sqrt", params: &["x"], func: Plain(complex_sqrt) },
+];
+
+/// Named values that variables of the same name shadow.
+pub struct Constant {
+    pub name: &'static str,
+    pub value: Value
+}
+
+static CONSTANTS: &'static [Constant] = &[
+    Constant { name: "pi",  value: Value::Float(std::f64::consts::PI) },
+    Constant { name: "e",   value: Value::Float(std::f64::consts::E) },
+    Constant { name: "tau", value: Value::Float(std::f64::consts::TAU) },
+    Constant { name: "i",   value: Value::Complex(Complex::I) },
+];
+
+pub fn constant(name: &str) -> Option<&'static Value> {
+    CONSTANTS
+        .iter()
+        .find(|constant| constant.name == name)
+        .map(|constant| &constant.value)
+}
+
+pub fn constants() -> &'static [Constant] {
+    CONSTANTS
+}
+
+/// The builtin a call to `name` runs in `mode`.
+pub fn resolve(name: &str, mode: Mode) -> Option<&'static Builtin> {
+    let complex = COMPLEX
+        .iter()
+        .find(|func| func.name == name);
+    match (mode, complex) {
+        (Mode::Complex, Some(func)) => Some(func),
+        _ => lookup(name).map(|(_, func)| func),
+    }
+}
+
+pub fn fold(name: &str) -> Option<&'static Fold> {
+    FOLDS
+        .iter()
+        .find(|fold| fold.name == name)
+}
+
+pub fn lookup(name: &str) -> Option<(usize, &'static Builtin)> {
+    BUILTINS
+        .iter()
+        .enumerate()
+        .find(|(_, func)| func.name == name)
+}
+
+pub fn all() -> &'static [Builtin] {
+    BUILTINS
+}
+
+impl Builtin {
+    /// Whether it calls function values, which only the tree walker can.
+    pub fn is_higher(&self) -> bool {
+        matches!(self.func, Higher(..))
+    }
+    pub fn call(&self, args: &[Value], apply: Option<&Apply>) -> Result<Value, EvalError> {
+        if args.len() != self.params.len() {
+            return Err(EvalError::Arity {
+                name: self.name.to_string(),
+                expect: self.params.len(),
+                found: args.len()
+            })
+        }
+        match (&self.func, apply) {
+            (Plain(func), _) => func(args),
+            (Higher(func), Some(apply)) => func(args, apply),
+            (Higher(..), None) => Err(EvalError::Unsupported(format!("{} here", self.name))),
+        }
+    }
+}
+
+impl Fold {
+    pub fn run(&self, mut items: impl Iterator<Item = Result<Value, EvalError>>) -> Result<Value, EvalError> {
+        items.try_fold(Value::Num(self.init), |acc, item| (self.func)(acc, item?))
+    }
+}
+
+fn num(name: &str, val: &Value) -> Result<i64, EvalError> {
+    match val {
+        Value::Num(n) => Ok(*n),
+        val => Err(EvalError::Unsupported(format!("{} on {}", name, val))),
+    }
+}
+
+fn float(name: &str, val: &Value) -> Result<f64, EvalError> {
+    match val {
+        Value::Num(n) => Ok(*n as f64),
+        Value::Float(x) => Ok(*x),
+        val => Err(EvalError::Unsupported(format!("{} on {}", name, val))),
+    }
+}
+
+fn abs(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Float(x) => Ok(Value::Float(x.abs())),
+        Value::Complex(z) => Ok(Value::Float(z.abs())),
+        val => num("abs", val)?.checked_abs().map(Value::Num).ok_or(EvalError::Overflow),
+    }
+}
+fn min(args: &[Value]) -> Result<Value, EvalError> {
+    match (&args[0], &args[1]) {
+        (Value::Num(a), Value::Num(b)) => Ok(Value::Num(*a.min(b))),
+        (a, b) => Ok(Value::Float(float("min", a)?.min(float("min", b)?))),
+    }
+}
+fn max(args: &[Value]) -> Result<Value, EvalError> {
+    match (&args[0], &args[1]) {
+        (Value::Num(a), Value::Num(b)) => Ok(Value::Num(*a.max(b))),
+        (a, b) => Ok(Value::Float(float("max", a)?.max(float("max", b)?))),
+    }
+}
+
+fn gcd(args: &[Value]) -> Result<Value, EvalError> {
+    let (mut a, mut b) = (num("gcd", &args[0])?, num("gcd", &args[1])?);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.checked_abs().map(Value::Num).ok_or(EvalError::Overflow)
+}
+
+fn sqrt(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Complex(z) => Ok(z.sqrt().value()),
+        val => Ok(Value::Float(float("sqrt", val)?.sqrt())),
+    }
+}
+/// `sqrt(-1)` is `i`.
+fn complex_sqrt(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Num(..) | Value::Float(..) if float("sqrt", &args[0])? >= 0.0 => sqrt(args),
+        val => Ok(Value::Complex(as_complex("sqrt", val)?.sqrt())),
+    }
+}
+
+fn as_complex(name: &str, val: &Value) -> Result<Complex, EvalError> {
+    Complex::from_value(val).ok_or_else(|| EvalError::Unsupported(format!("{} on {}", name, val)))
+}
+fn re(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        val @ (Value::Num(..) | Value::Float(..)) => Ok(val.clone()),
+        val => Ok(Value::Float(as_complex("re", val)?.re)),
+    }
+}
+fn im(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Num(..) => Ok(Value::Num(0)),
+        val => Ok(Value::Float(as_complex("im", val)?.im)),
+    }
+}
+fn arg(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Float(as_complex("arg", &args[0])?.arg()))
+}
+fn conj(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Complex(z) => Ok(Value::Complex(z.conj())),
+        val => as_complex("conj", val).map(|_| val.clone()),
+    }
+}
+
+fn round_with(name: &str, val: &Value, f: fn(f64) -> f64) -> Result<Value, EvalError> {
+    match val {
+        Value::Num(n) => Ok(Value::Num(*n)),
+        val => Ok(Value::Num(f(float(name, val)?) as i64)),
+    }
+}
+fn floor(args: &[Value]) -> Result<Value, EvalError> { round_with("floor", &args[0], f64::floor) }
+fn ceil (args: &[Value]) -> Result<Value, EvalError> { round_with("ceil",  &args[0], f64::ceil) }
+fn round(args: &[Value]) -> Result<Value, EvalError> { round_with("round", &args[0], f64::round) }
+
+fn fold_seq(name: &str, seq: &Value) -> Result<Value, EvalError> {
+    let items = match seq.items() {
+        Some(items) => items,
+        None => return Err(EvalError::Unsupported(format!("{} on {}", name, seq))),
+    };
+    let items = items
+        .enumerate()
+        .map(|(i, item)| match i < MAX_ITERATIONS {
+            true => Ok(item),
+            false => Err(EvalError::TooLong),
+        });
+    fold(name).unwrap().run(items)
+}
+fn fold_sum  (args: &[Value]) -> Result<Value, EvalError> { fold_seq("sum",   &args[0]) }
+fn fold_prod (args: &[Value]) -> Result<Value, EvalError> { fold_seq("prod",  &args[0]) }
+fn fold_count(args: &[Value]) -> Result<Value, EvalError> { fold_seq("count", &args[0]) }
+fn fold_any  (args: &[Value]) -> Result<Value, EvalError> { fold_seq("any",   &args[0]) }
+fn fold_all  (args: &[Value]) -> Result<Value, EvalError> { fold_seq("all",   &args[0]) }
+
+fn step(args: &[Value]) -> Result<Value, EvalError> {
+    let range = match &args[0] {
+        Value::Range(range) => range,
+        val => return Err(EvalError::Unsupported(format!("step on {}", val))),
+    };
+    match num("step", &args[1])? {
+        0 => Err(EvalError::Unsupported(format!("step of 0"))),
+        step => Ok(Value::Range(Range { step, ..range.clone() })),
+    }
+}
+
+fn len(args: &[Value]) -> Result<Value, EvalError> {
+    match args[0].len() {
+        Some(len) => Ok(Value::Num(len as i64)),
+        None => Err(EvalError::Unsupported(format!("len on {}", args[0]))),
+    }
+}
+
+/// Items of a list or range, at most `MAX_ITERATIONS` of them.
+fn list(name: &str, seq: &Value) -> Result<Vec<Value>, EvalError> {
+    match seq.items() {
+        Some(items) => {
+            let items = items
+                .take(MAX_ITERATIONS + 1)
+                .collect::<Vec<_>>();
+            match items.len() > MAX_ITERATIONS {
+                true => Err(EvalError::TooLong),
+                false => Ok(items),
+            }
+        }
+        None => Err(EvalError::Unsupported(format!("{} on {}", name, seq))),
+    }
+}
+
+/// Order of numbers, or of strings.
+fn compare(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    let ord = match (a, b) {
+        (Value::Num(a), Value::Num(b)) => Some(a.cmp(b)),
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        (Value::Num(..) | Value::Float(..), Value::Num(..) | Value::Float(..)) => {
+            float("compare", a)?.partial_cmp(&float("compare", b)?)
+        }
+        _ => None,
+    };
+    ord.ok_or_else(|| EvalError::Unsupported(format!("comparing {} and {}", a, b)))
+}
+
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::List(a), Value::List(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+        (Value::Range(a), Value::Range(b)) => a == b,
+        (Value::Complex(a), Value::Complex(b)) => a == b,
+        (Value::Matrix(a), Value::Matrix(b)) => {
+            a.shape() == b.shape() && a.items.iter().zip(&b.items).all(|(a, b)| same(a, b))
+        }
+        (Value::Func(a), Value::Func(b)) => Arc::ptr_eq(a, b),
+        (a, b) => compare(a, b).is_ok_and(Ordering::is_eq),
+    }
+}
+
+fn sort_with(mut items: Vec<(Value, Value)>) -> Result<Value, EvalError> {
+    let mut err = None;
+    items.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or_else(|e| {
+        err.get_or_insert(e);
+        Ordering::Equal
+    }));
+    match err {
+        Some(err) => Err(err),
+        None => Ok(Value::List(items.into_iter().map(|(_, item)| item).collect())),
+    }
+}
+
+fn map(args: &[Value], apply: &Apply) -> Result<Value, EvalError> {
+    list("map", &args[0])?
+        .into_iter()
+        .map(|item| apply(&args[1], vec![item]))
+        .collect::<Result<_, _>>()
+        .map(Value::List)
+}
+fn filter(args: &[Value], apply: &Apply) -> Result<Value, EvalError> {
+    let mut kept = Vec::new();
+    for item in list("filter", &args[0])? {
+        if apply(&args[1], vec![item.clone()])?.truthy()? {
+            kept.push(item)
+        }
+    }
+    Ok(Value::List(kept))
+}
+fn reduce(args: &[Value], apply: &Apply) -> Result<Value, EvalError> {
+    let mut items = list("reduce", &args[0])?.into_iter();
+    let first = match items.next() {
+        Some(first) => first,
+        None => return Err(EvalError::Unsupported(format!("reduce on {}", args[0]))),
+    };
+    items.try_fold(first, |acc, item| apply(&args[1], vec![acc, item]))
+}
+fn sort_by(args: &[Value], apply: &Apply) -> Result<Value, EvalError> {
+    let items = list("sort_by", &args[0])?
+        .into_iter()
+        .map(|item| Ok((apply(&args[1], vec![item.clone()])?, item)))
+        .collect::<Result<_, EvalError>>()?;
+    sort_with(items)
+}
+
+/// Pairs of items, as long as the shorter list.
+fn zip(args: &[Value]) -> Result<Value, EvalError> {
+    let pairs = list("zip", &args[0])?
+        .into_iter()
+        .zip(list("zip", &args[1])?)
+        .map(|(a, b)| Value::List(vec![a, b]))
+        .collect();
+    Ok(Value::List(pairs))
+}
+fn sort(args: &[Value]) -> Result<Value, EvalError> {
+    let items = list("sort", &args[0])?
+        .into_iter()
+        .map(|item| (item.clone(), item))
+        .collect();
+    sort_with(items)
+}
+/// Items without repeats, in order of first appearance.
+fn unique(args: &[Value]) -> Result<Value, EvalError> {
+    let mut kept: Vec<Value> = Vec::new();
+    for item in list("unique", &args[0])? {
+        if !kept.iter().any(|seen| same(seen, &item)) {
+            kept.push(item)
+        }
+    }
+    Ok(Value::List(kept))
+}
+fn reverse(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Str(s) => Ok(Value::Str(s.chars().rev().collect())),
+        seq => {
+            let mut items = list("reverse", seq)?;
+            items.reverse();
+            Ok(Value::List(items))
+        }
+    }
+}
+
+/// A matrix, or a list of rows that makes one.
+fn matrix(name: &str, val: &Value) -> Result<Matrix, EvalError> {
+    let matrix = match val {
+        Value::Matrix(matrix) => Some(matrix.clone()),
+        Value::List(rows) => Matrix::from_rows(rows),
+        _ => None,
+    };
+    matrix.ok_or_else(|| EvalError::Unsupported(format!("{} on {}", name, val)))
+}
+
+fn shape(args: &[Value]) -> Result<Value, EvalError> {
+    let matrix = matrix("shape", &args[0])?;
+    Ok(Value::List(vec![Value::Num(matrix.rows as i64), Value::Num(matrix.cols as i64)]))
+}
+fn transpose(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Matrix(matrix("transpose", &args[0])?.transpose()))
+}
+fn det(args: &[Value]) -> Result<Value, EvalError> {
+    matrix("det", &args[0])?.det()
+}
+fn inverse(args: &[Value]) -> Result<Value, EvalError> {
+    matrix("inverse", &args[0])?.inverse().map(Value::Matrix)
+}
+fn identity(args: &[Value]) -> Result<Value, EvalError> {
+    match num("identity", &args[0])? {
+        n if n < 1 => Err(EvalError::Unsupported(format!("identity of size {}", n))),
+        n => Matrix::identity(n as usize).map(Value::Matrix),
+    }
+}
+/// `x` in `a * x == b`, for a matrix `b` or a vector, which gives a vector.
+fn solve(args: &[Value]) -> Result<Value, EvalError> {
+    let a = matrix("solve", &args[0])?;
+    match &args[1] {
+        Value::List(items) if items.iter().all(|item| matches!(item, Value::Num(..) | Value::Float(..))) => {
+            let x = a.solve(&Matrix::column(items.clone()))?;
+            Ok(Value::List(x.items))
+        }
+        b => a.solve(&matrix("solve", b)?).map(Value::Matrix),
+    }
+}
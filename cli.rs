@@ -0,0 +1,205 @@
+use crate::editor::Keymap;
+use crate::settings::Radix;
+
+pub static USAGE: &'static str = "\
+usage: calc [options] [script]
+
+Evaluates each line of script, of stdin when it is not a terminal, or of
+every -e expression. Starts the interactive prompt otherwise.
+
+options:
+  -e, --expr EXPR       evaluate EXPR, may be repeated
+  --show LIST           outputs to print, from tokens,ast,tree,value
+  --format plain|json   output format, --json for short
+  --radix bin|oct|dec|hex
+                        radix of integer results
+  --precision N         digits after the point of float results
+  --keep-going          report every error instead of stopping at the first
+  --bench ROWS          time the tree walker against the VM over ROWS rows
+  --keymap emacs|vi     key bindings of the interactive prompt
+  -I, --path DIR        look for modules in DIR too, may be repeated
+  --session FILE        load FILE if it exists, and autosave the prompt's
+                        session to it
+  -h, --help            print this help
+
+exit status: 0 success, 1 i/o error, 2 usage error, 3 parse error, 4 eval error
+";
+
+pub const EXIT_IO: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_PARSE: u8 = 3;
+pub const EXIT_EVAL: u8 = 4;
+
+#[derive(Clone, Copy)]
+pub struct Show {
+    pub tokens: bool,
+    pub ast: bool,
+    pub tree: bool,
+    pub value: bool
+}
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Plain,
+    Json,
+}
+
+pub struct Options {
+    pub exprs: Vec<String>,
+    pub path: Option<String>,
+    pub show: Option<Show>,
+    pub format: Format,
+    pub radix: Option<Radix>,
+    pub precision: Option<usize>,
+    pub keep_going: bool,
+    pub bench: Option<usize>,
+    pub keymap: Keymap,
+    pub session: Option<String>,
+    pub search: Vec<String>,
+    pub help: bool
+}
+
+impl Show {
+    pub fn value() -> Self {
+        Self { tokens: false, ast: false, tree: false, value: true }
+    }
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut show = Self { tokens: false, ast: false, tree: false, value: false };
+        for item in list.split(',') {
+            match item.trim() {
+                "tokens" => show.tokens = true,
+                "ast" => show.ast = true,
+                "tree" => show.tree = true,
+                "value" => show.value = true,
+                item => return Err(format!("unknow output {}", item)),
+            }
+        }
+        Ok(show)
+    }
+    /// Only the value is printed, so it goes out without a label.
+    pub fn bare(&self) -> bool {
+        self.value && !self.tokens && !self.ast && !self.tree
+    }
+}
+
+fn value<I: Iterator<Item=String>>(flag: &str, inline: Option<String>, args: &mut I) -> Result<String, String> {
+    match inline.or_else(|| args.next()) {
+        Some(val) => Ok(val),
+        None => Err(format!("{} expect a value", flag)),
+    }
+}
+
+pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    let mut opts = Options {
+        exprs: Vec::new(),
+        path: None,
+        show: None,
+        format: Format::Plain,
+        radix: None,
+        precision: None,
+        keep_going: false,
+        bench: None,
+        keymap: Keymap::Emacs,
+        session: None,
+        search: Vec::new(),
+        help: false
+    };
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, val)) if flag.starts_with("--") => (flag.to_string(), Some(val.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || value(&flag, inline.clone(), &mut args);
+        match flag.as_str() {
+            "-e" | "--expr" => opts.exprs.push(value()?),
+            "--show" => opts.show = Some(Show::parse(&value()?)?),
+            "--format" => opts.format = match value()?.as_str() {
+                "plain" => Format::Plain,
+                "json" => Format::Json,
+                format => return Err(format!("unknow format {}", format)),
+            },
+            "--json" => opts.format = Format::Json,
+            "--radix" => {
+                let radix = value()?;
+                match Radix::from_name(&radix) {
+                    Some(radix) => opts.radix = Some(radix),
+                    None => return Err(format!("unknow radix {}", radix)),
+                }
+            }
+            "--precision" => {
+                let precision = value()?;
+                match precision.parse() {
+                    Ok(precision) => opts.precision = Some(precision),
+                    Err(..) => return Err(format!("invalid precision {}", precision)),
+                }
+            }
+            "--keep-going" => opts.keep_going = true,
+            "--bench" => {
+                let rows = value()?;
+                match rows.parse() {
+                    Ok(rows) => opts.bench = Some(rows),
+                    Err(..) => return Err(format!("invalid row count {}", rows)),
+                }
+            }
+            "--keymap" => {
+                let keymap = value()?;
+                match Keymap::from_name(&keymap) {
+                    Some(keymap) => opts.keymap = keymap,
+                    None => return Err(format!("unknow keymap {}", keymap)),
+                }
+            }
+            "--session" => opts.session = Some(value()?),
+            "-I" | "--path" => opts.search.push(value()?),
+            "-h" | "--help" => opts.help = true,
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknow option {}", flag)),
+            _ if opts.path.is_some() => return Err(format!("expect one script, found {}", flag)),
+            _ => opts.path = Some(flag),
+        }
+    }
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn reads_flags_and_values() {
+        let opts = parse_args(&["-e", "1 + 1", "--expr=2", "--show", "ast, value", "--json", "--keep-going", "-I", "lib"]).ok().unwrap();
+        assert_eq!(opts.exprs, ["1 + 1", "2"]);
+        assert!(matches!(opts.show, Some(Show { tokens: false, ast: true, tree: false, value: true })));
+        assert!(matches!(opts.format, Format::Json));
+        assert!(opts.keep_going);
+        assert_eq!(opts.search, ["lib"]);
+        assert!(opts.path.is_none());
+    }
+
+    #[test]
+    fn takes_one_script() {
+        assert_eq!(parse_args(&["a.calc"]).ok().unwrap().path.as_deref(), Some("a.calc"));
+        assert_eq!(parse_args(&["-"]).ok().unwrap().path.as_deref(), Some("-"));
+        assert_eq!(parse_args(&["a.calc", "b.calc"]).err().unwrap(), "expect one script, found b.calc");
+    }
+
+    #[test]
+    fn rejects_bad_usage() {
+        let err = |args: &[&str]| parse_args(args).err().unwrap();
+        assert_eq!(err(&["--nope"]), "unknow option --nope");
+        assert_eq!(err(&["-e"]), "-e expect a value");
+        assert_eq!(err(&["--format", "xml"]), "unknow format xml");
+        assert_eq!(err(&["--radix=ten"]), "unknow radix ten");
+        assert_eq!(err(&["--precision", "-1"]), "invalid precision -1");
+        assert_eq!(err(&["--bench", "many"]), "invalid row count many");
+        assert_eq!(err(&["--show", "value,color"]), "unknow output color");
+    }
+
+    #[test]
+    fn bare_output_is_the_value_alone() {
+        assert!(Show::value().bare());
+        assert!(!Show::parse("value,tokens").ok().unwrap().bare());
+    }
+}
@@ -0,0 +1,449 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::builtin;
+use crate::dot;
+use crate::env::Env;
+use crate::lexer::Lexer;
+use crate::fold::Folder;
+use crate::node::{Node, Stmt, Value, BinaryOp};
+use crate::parser::{Parser, Result};
+use crate::render;
+use crate::sexpr;
+use crate::settings::{Radix, Mode, Form};
+use crate::token::Token;
+
+pub static HELP: &'static str = "\
+commands:
+  :help               print this help
+  :tokens EXPR        print the tokens of EXPR
+  :ast EXPR           print the tree of EXPR as an S-expression
+  :read FORM          evaluate a tree written as :ast prints it
+  :tree EXPR          draw the tree of EXPR
+  :dot EXPR           print the tree of EXPR as Graphviz source
+  :latex EXPR         print EXPR as LaTeX
+  :mathml EXPR        print EXPR as MathML
+  :time EXPR          evaluate EXPR and print how long it took
+  :vars               list variables
+  :funcs              list user and builtin functions
+  :clear              forget every variable and function
+  :load FILE          run the lines of FILE, warning about names it replaces
+  :save FILE          write settings, variables and functions to FILE
+  :set radix R        print integers in bin, oct, dec or hex
+  :set precision N    print floats with N digits, or off
+  :set mode M         exact integer arithmetic, float, or complex
+  :set form F         print complex numbers in rect or polar form
+  :set autosave FILE  save the session to FILE after every line, or off
+  .                   quit
+
+Tab completes variables, functions, constants and commands.
+
+statements:
+  EXPR                evaluate
+  x = EXPR            assign a variable
+  f(x, y) = EXPR      define a function
+  import \"FILE\"       run module FILE once, binding its names as STEM.name
+  use A::B::f         import module A/B.calc as B, and f unqualified
+  use A::B::*         import module A/B.calc, every name unqualified
+  ans, _N             the last result, the Nth result
+  * EXPR              a leading binary operator applies to ans
+
+expressions:
+  == != < <= > >=     compare, giving 1 or 0
+  && || !             and, or, not; && and || skip their right side
+  if C then A else B  A when C is not 0, else B
+  C ? A : B           the same
+  a..b, a..=b         integers from a to b, without or with b
+  step(R, n)          every nth integer of range R
+  [a, b, c]           a list; operators apply item by item
+  xs[i], xs[a..b]     item i, counted from the end when negative, or a slice
+  xs ++ ys            join lists or strings, or stack the rows of matrices
+  E for x in R        list of E for each x of R, also inside sum, prod, ...;
+                      one for per loop, nest loops with parentheses
+  |x, y| E            a function value, keeping the local variables it uses
+  map(xs, f)          f of each item; also filter, reduce, sort_by
+  zip(xs, ys)         pairs of items; also sort, unique, reverse
+  [[1, 2], [3, 4]]    a matrix, from rows of the same length
+  A * B, A * v        matrix product; other operators go item by item
+  det(A), inverse(A)  also transpose, shape, identity(n)
+  solve(A, b)         x such that A * x == b
+  2i, 1 + 2i, i       complex numbers; i is a constant variables can shadow
+  re, im, abs, arg    parts, magnitude and angle of z; also conj(z)
+";
+
+/// Command names, for completion.
+pub static COMMANDS: &'static [&'static str] = &[
+    "help", "tokens", "ast", "read", "tree", "dot", "latex", "mathml", "time",
+    "vars", "funcs", "clear", "load", "save", "set",
+];
+
+/// Settings `:set` knows, for completion.
+pub static SETTINGS: &'static [&'static str] = &["radix", "precision", "mode", "form", "autosave"];
+
+fn parse(src: &str) -> std::result::Result<Stmt, String> {
+    match Parser::new(Lexer::new(src.chars()).lenient()).parse_stmt() {
+        Result::None => Err(format!("expect expression")),
+        Result::Ok(stmt) => Ok(stmt),
+        Result::Err(err) | Result::Incomplete(err) => Err(err),
+    }
+}
+
+fn parse_expr(src: &str) -> std::result::Result<Node, String> {
+    match Parser::new(Lexer::new(src.chars()).lenient()).parse() {
+        Result::None => Err(format!("expect expression")),
+        Result::Ok(node) => Ok(node),
+        Result::Err(err) | Result::Incomplete(err) => Err(err),
+    }
+}
+
+/// Run the colon command `cmd`, given without its colon.
+pub fn run(env: &mut Env, cmd: &str) -> std::result::Result<(), String> {
+    let (name, arg) = cmd
+        .split_once(' ')
+        .unwrap_or((cmd, ""));
+    let arg = arg.trim();
+    match name {
+        "help" => print!("{}", HELP),
+
+        "tokens" => {
+            let toks = Lexer::new(arg.chars())
+                .lenient()
+                .collect::<Vec<_>>();
+            if let Some(err) = toks.iter().find_map(Token::error) {
+                return Err(err)
+            }
+            toks.iter().for_each(|tok| print!("{} ", tok));
+            println!()
+        }
+        "ast" => println!("{}", parse(arg)?),
+        "read" => match sexpr::read(arg)?.eval(env) {
+            Ok(val) => println!("{}", env.settings.show(&val)),
+            Err(err) => return Err(err.to_string()),
+        },
+        "tree" => {
+            let stmt = parse(arg)?;
+            match stmt.node() {
+                Some(node) => crate::print(node),
+                None => println!("{}", stmt),
+            }
+        }
+        "dot" => print!("{}", dot::dot(&parse_expr(arg)?, Some(env))),
+        "latex" => println!("{}", render::latex(&parse_expr(arg)?)),
+        "mathml" => println!("{}", render::mathml(&parse_expr(arg)?)),
+
+        "time" => {
+            let stmt = parse(arg)?;
+            let start = Instant::now();
+            let res = stmt.exec(env);
+            let elapsed = start.elapsed();
+            match res {
+                Ok(Some(val)) => println!("eval: {}", env.settings.show(&val)),
+                Ok(None) => {}
+                Err(err) => println!("error: {}", err),
+            }
+            println!("time: {:?}", elapsed)
+        }
+
+        "vars" => env
+            .vars()
+            .into_iter()
+            .for_each(|(name, val)| println!("{} = {}", name, env.settings.show(val))),
+        "funcs" => {
+            env.funcs()
+                .into_iter()
+                .for_each(|(name, func)| println!("{}({}) = {}", name, func.params.join(", "), func.body.infix()));
+            builtin::all()
+                .iter()
+                .for_each(|func| println!("{}({})", func.name, func.params.join(", ")))
+        }
+        "clear" => env.clear(),
+
+        "load" => load(env, arg)?,
+        "save" => save(env, arg)?,
+
+        "set" => {
+            let (key, val) = arg
+                .split_once(' ')
+                .unwrap_or((arg, ""));
+            let val = val.trim();
+            match key {
+                "radix" => match Radix::from_name(val) {
+                    Some(radix) => env.settings.radix = radix,
+                    None => return Err(format!("unknow radix {}", val)),
+                },
+                "precision" if val == "off" => env.settings.precision = None,
+                "precision" => match val.parse() {
+                    Ok(precision) => env.settings.precision = Some(precision),
+                    Err(..) => return Err(format!("invalid precision {}", val)),
+                },
+                "mode" => match Mode::from_name(val) {
+                    Some(mode) => env.settings.mode = mode,
+                    None => return Err(format!("unknow mode {}", val)),
+                },
+                "form" => match Form::from_name(val) {
+                    Some(form) => env.settings.form = form,
+                    None => return Err(format!("unknow form {}", val)),
+                },
+                "autosave" if val == "off" => env.settings.autosave = None,
+                "autosave" if val.is_empty() => return Err(format!("autosave expect a file or off")),
+                "autosave" => env.settings.autosave = Some(val.to_string()),
+                "" => {
+                    let precision = env.settings.precision.map_or(format!("off"), |precision| precision.to_string());
+                    let autosave = env.settings.autosave.as_deref().unwrap_or("off");
+                    println!("radix = {}", env.settings.radix);
+                    println!("precision = {}", precision);
+                    println!("mode = {}", env.settings.mode);
+                    println!("form = {}", env.settings.form);
+                    println!("autosave = {}", autosave)
+                }
+                key => return Err(format!("unknow setting {}", key)),
+            }
+        }
+
+        _ => return Err(format!("unknow command {}", name)),
+    }
+    Ok(())
+}
+
+/// Run every line of `path` as a statement or command, quietly. Blank lines
+/// and lines starting with `#` are skipped. Assignments and definitions that
+/// replace an existing name print a warning.
+pub fn load(env: &mut Env, path: &str) -> std::result::Result<(), String> {
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(err) => return Err(format!("{}: {}", path, err)),
+    };
+    env.modules
+        .enter(Path::new(path))
+        .map_err(|err| err.to_string())?;
+    let res = run_lines(env, path, &src);
+    env.modules.leave();
+    res
+}
+
+/// Run the lines of `src`, reporting each one that fails and going on with
+/// the next.
+fn run_lines(env: &mut Env, path: &str, src: &str) -> std::result::Result<(), String> {
+    let mut failed = 0;
+    for (index, line) in src.lines().enumerate() {
+        let res = if line.trim_start().starts_with('#') {
+            Ok(())
+        }
+        else if let Some(cmd) = line.strip_prefix(':') {
+            run(env, cmd)
+        }
+        else {
+            match Parser::new(Lexer::new(line.chars()).lenient()).parse_stmt() {
+                Result::None => Ok(()),
+                Result::Ok(stmt) => {
+                    if let Some(warning) = collision(env, &stmt) {
+                        eprintln!("warning: {}:{}: {}", path, index + 1, warning)
+                    }
+                    stmt.exec(env)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }
+                Result::Err(err) | Result::Incomplete(err) => Err(err),
+            }
+        };
+        if let Err(err) = res {
+            eprintln!("error: {}:{}: {}", path, index + 1, err);
+            failed += 1
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        1 => Err(format!("{}: 1 line failed", path)),
+        n => Err(format!("{}: {} lines failed", path, n)),
+    }
+}
+
+/// What `stmt` would silently replace or shadow.
+fn collision(env: &Env, stmt: &Stmt) -> Option<String> {
+    match stmt {
+        Stmt::Let(name, _) => match env.var(name) {
+            Some(old) => Some(format!("redefines {}, was {}", name, Node::Val(old.clone()).infix())),
+            None if builtin::constant(name).is_some() => Some(format!("{} shadows the constant of that name", name)),
+            None => None,
+        },
+        Stmt::Def(name, ..) => match env.func(name) {
+            Some(old) => Some(format!("redefines {}, was {}({}) = {}", name, name, old.params.join(", "), old.body.infix())),
+            None if builtin::lookup(name).is_some() => Some(format!("{} shadows the builtin function of that name", name)),
+            None => None,
+        },
+        Stmt::Expr(..) | Stmt::Import(..) | Stmt::Use(..) => None,
+    }
+}
+
+/// Write settings, variables and functions to `path` as lines `:load`
+/// reads back.
+pub fn save(env: &Env, path: &str) -> std::result::Result<(), String> {
+    let settings = &env.settings;
+    let precision = settings.precision.map_or(format!("off"), |precision| precision.to_string());
+    let mut src = String::from("# calc session\n");
+    src.push_str(&format!(":set radix {}\n", settings.radix));
+    src.push_str(&format!(":set precision {}\n", precision));
+    src.push_str(&format!(":set mode {}\n", settings.mode));
+    src.push_str(&format!(":set form {}\n", settings.form));
+    for (name, func) in env.funcs() {
+        src.push_str(&format!("{}({}) = {}\n", name, func.params.join(", "), func.body.infix()));
+    }
+    for (name, val) in env.vars() {
+        src.push_str(&format!("{} = {}\n", name, literal(val).infix()));
+    }
+    match fs::write(path, src) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("{}: {}", path, err)),
+    }
+}
+
+/// A tree that evaluates back to `val`. Non-finite floats, which have no
+/// literal, become divisions by zero, and function values have what they
+/// captured written into their bodies.
+fn literal(val: &Value) -> Node {
+    let div = |x: f64| Node::Binary(BinaryOp::Div, Box::new(Node::Val(Value::Float(x))), Box::new(Node::Val(Value::Float(0.0))));
+    match val {
+        Value::Float(x) if x.is_nan() => div(0.0),
+        Value::Float(x) if x.is_infinite() => div(x.signum()),
+        Value::List(items) => Node::List(items.iter().map(literal).collect()),
+        Value::Matrix(matrix) => Node::List(matrix.rows().map(|row| literal(&row)).collect()),
+        Value::Func(closure) => {
+            let mut captured = Captured { vars: &closure.captured, bound: closure.params.clone() };
+            Node::Lambda(closure.params.clone(), Box::new(captured.fold_node(closure.body.clone())))
+        }
+        val => Node::Val(val.clone()),
+    }
+}
+
+/// Replaces the captured variables of a closure by their values. A captured
+/// function that is called, as in `f(x)`, keeps its name.
+struct Captured<'a> {
+    vars: &'a [(String, Value)],
+    /// Parameters and loop variables in reach, which shadow the captures.
+    bound: Vec<String>
+}
+
+impl Folder for Captured<'_> {
+    fn fold_var(&mut self, name: String) -> Node {
+        match self.vars.iter().rev().find(|(var, _)| *var == name) {
+            Some((_, val)) if !self.bound.contains(&name) => literal(val),
+            _ => Node::Var(name),
+        }
+    }
+    fn fold_for(&mut self, var: String, seq: Node, body: Node) -> Node {
+        let seq = self.fold_node(seq);
+        self.bound.push(var.clone());
+        let body = self.fold_node(body);
+        self.bound.pop();
+        Node::For(var, Box::new(seq), Box::new(body))
+    }
+    fn fold_lambda(&mut self, params: Vec<String>, body: Node) -> Node {
+        let len = self.bound.len();
+        self.bound.extend(params.iter().cloned());
+        let body = self.fold_node(body);
+        self.bound.truncate(len);
+        Node::Lambda(params, Box::new(body))
+    }
+}
+
+/// Save to the autosave file, if one is set.
+pub fn autosave(env: &Env) -> std::result::Result<(), String> {
+    match &env.settings.autosave {
+        Some(path) => save(env, path),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::tests::run as exec;
+
+    #[test]
+    fn reports_unknown_characters() {
+        let mut env = Env::new();
+        for cmd in ["ast 1 $ 2", "tree $", "dot 1 @", "latex (1", "tokens 1 $", "time x = $", "read (Add 1"] {
+            assert!(run(&mut env, cmd).is_err(), "{}", cmd);
+        }
+        assert_eq!(run(&mut env, "tokens $").err().unwrap(), "unknow character $");
+        assert_eq!(run(&mut env, "ast 1 $ 2").err().unwrap(), "unknow character $");
+        assert!(run(&mut env, "tokens 1 + x").is_ok());
+        assert!(run(&mut env, "read (Mul 2 (Add 1 2))").is_ok());
+    }
+
+    #[test]
+    fn sets_settings() {
+        let mut env = Env::new();
+        assert!(run(&mut env, "set mode float").is_ok());
+        assert_eq!(exec(&mut env, "7 / 2"), "3.5");
+        assert!(run(&mut env, "set precision 3").is_ok());
+        assert_eq!(env.settings.precision, Some(3));
+        assert!(run(&mut env, "set precision off").is_ok());
+        assert_eq!(env.settings.precision, None);
+        assert_eq!(run(&mut env, "set mode fuzzy").err().unwrap(), "unknow mode fuzzy");
+        assert_eq!(run(&mut env, "set precision x").err().unwrap(), "invalid precision x");
+        assert_eq!(run(&mut env, "set colour on").err().unwrap(), "unknow setting colour");
+        assert_eq!(run(&mut env, "frobnicate").err().unwrap(), "unknow command frobnicate");
+    }
+
+    #[test]
+    fn clear_forgets_names_but_keeps_settings() {
+        let mut env = Env::new();
+        run(&mut env, "set mode float").unwrap();
+        exec(&mut env, "x = 1");
+        exec(&mut env, "f(x) = x");
+        run(&mut env, "clear").unwrap();
+        assert!(env.get("x").is_none() && env.func("f").is_none());
+        assert!(matches!(env.settings.mode, Mode::Float));
+    }
+
+    #[test]
+    fn warns_about_replaced_names() {
+        let mut env = Env::new();
+        exec(&mut env, "x = 1");
+        exec(&mut env, "f(a) = a + 1");
+        let warning = |env: &Env, src| collision(env, &crate::parser::tests::stmt(src));
+        assert_eq!(warning(&env, "x = 2").as_deref(), Some("redefines x, was 1"));
+        assert_eq!(warning(&env, "f(b) = b").as_deref(), Some("redefines f, was f(a) = a + 1"));
+        assert_eq!(warning(&env, "pi = 3").as_deref(), Some("pi shadows the constant of that name"));
+        assert_eq!(warning(&env, "sqrt(x) = x").as_deref(), Some("sqrt shadows the builtin function of that name"));
+        assert_eq!(warning(&env, "y = 2"), None);
+    }
+
+    fn temp(name: &str) -> String {
+        std::env::temp_dir().join(format!("calc-{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn saved_sessions_load_back() {
+        let path = temp("saved.calc");
+        let mut env = Env::new();
+        run(&mut env, "set precision 4").unwrap();
+        for src in ["n = 0.0 / 0.0", "p = 1.0 / 0.0", "m = -1.0 / 0.0", "xs = [1, n, [p, 2]]", "k = 10", "half = 0.5", "sq(x) = x * x + k",
+                    "adders = map([1, 2], |a| |x| x + a + k)", "add = adders[1]", "shadow = |k| k + 1", "loop = |n| sum(k * k for k in 1..=n)", "mat = [[1, 2], [3, 4.5]]"] {
+            exec(&mut env, src);
+        }
+        run(&mut env, &format!("save {}", path)).unwrap();
+        let mut loaded = Env::new();
+        load(&mut loaded, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.settings.precision, Some(4));
+        for (src, res) in [("n", "NaN"), ("p", "inf"), ("m", "-inf"), ("xs", "[1, NaN, [inf, 2]]"), ("half", "0.5"), ("sq(2)", "14"),
+                           ("add(1)", "13"), ("shadow(1)", "2"), ("loop(2)", "5"),
+                           ("mat", "[[1, 2], [3, 4.5]]"), ("det(mat)", "-1.5")] {
+            assert_eq!(exec(&mut loaded, src), res, "{}", src);
+        }
+    }
+
+    #[test]
+    fn loading_goes_on_past_bad_lines() {
+        let path = temp("bad.calc");
+        std::fs::write(&path, "a = 1\nb = (\nd = a + 1\ne = $\n").unwrap();
+        let mut env = Env::new();
+        let res = load(&mut env, &path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(res.err().unwrap(), format!("{}: 2 lines failed", path));
+        assert_eq!(exec(&mut env, "d"), "2");
+    }
+}
@@ -0,0 +1,133 @@
+use crate::builtin;
+use crate::command;
+use crate::editor::Candidate;
+use crate::env::Env;
+
+/// Completes REPL input from the names an environment knows.
+pub struct Completer<'a> {
+    env: &'a Env
+}
+
+impl <'a> Completer<'a> {
+    pub fn new(env: &'a Env) -> Self {
+        Self { env }
+    }
+    fn names(&self, word: &str) -> Vec<Candidate> {
+        let vars = self.env
+            .vars()
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .chain((self.env.result_count() > 0).then(|| format!("ans")))
+            .chain(builtin::constants().iter().map(|constant| constant.name.to_string()))
+            .map(|name| Candidate { text: name.clone(), display: name });
+        let funcs = self.env
+            .funcs()
+            .into_iter()
+            .map(|(name, func)| (name.clone(), func.params.join(", ")))
+            .chain(builtin::all().iter().map(|func| (func.name.to_string(), func.params.join(", "))))
+            .map(|(name, params)| Candidate {
+                text: format!("{}(", name),
+                display: format!("{}({})", name, params)
+            });
+        let mut cands = vars
+            .chain(funcs)
+            .filter(|cand| cand.text.starts_with(word))
+            .collect::<Vec<_>>();
+        cands.sort_by(|a, b| a.text.cmp(&b.text));
+        cands.dedup_by(|a, b| a.text == b.text);
+        cands
+    }
+}
+
+fn words(list: &[&str], word: &str, suffix: &str) -> Vec<Candidate> {
+    list.iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| Candidate { text: format!("{}{}", name, suffix), display: name.to_string() })
+        .collect()
+}
+
+fn is_name(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+impl Completer<'_> {
+    /// Start of the word before byte offset `pos` of `line`, and its
+    /// candidates.
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<Candidate>) {
+        let before = &line[..pos];
+        let start = before
+            .rfind(|c: char| !is_name(c))
+            .map_or(0, |index| index + 1);
+        let word = &before[start..];
+        if let Some(cmd) = before.strip_prefix(':') {
+            match cmd.split_once(' ') {
+                None => return (start, words(command::COMMANDS, word, " ")),
+                Some(("set", rest)) if !rest.trim_start().contains(' ') => {
+                    return (start, words(command::SETTINGS, word, " "))
+                }
+                Some(("load" | "save", ..)) => return (start, Vec::new()),
+                Some(..) => {}
+            }
+        }
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return (start, Vec::new())
+        }
+        (start, self.names(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::tests::run;
+    use crate::node::Value;
+
+    fn texts(env: &Env, line: &str) -> (usize, Vec<String>) {
+        let (start, cands) = Completer::new(env).complete(line, line.len());
+        (start, cands.into_iter().map(|cand| cand.text).collect())
+    }
+
+    #[test]
+    fn completes_names_in_order() {
+        let mut env = Env::new();
+        run(&mut env, "speed = 3");
+        run(&mut env, "sq(x) = x * x");
+        assert_eq!(texts(&env, "1 + sq"), (4, vec!["sq(".to_string(), "sqrt(".to_string()]));
+        assert_eq!(texts(&env, "1 + sp").1, ["speed"]);
+        assert_eq!(texts(&env, "p").1, ["pi", "prod("]);
+    }
+
+    #[test]
+    fn offers_ans_once_there_is_a_result() {
+        let mut env = Env::new();
+        assert!(texts(&env, "ans").1.is_empty());
+        env.push_result(Value::Num(1));
+        assert_eq!(texts(&env, "ans").1, ["ans"]);
+    }
+
+    #[test]
+    fn shows_parameters() {
+        let env = Env::new();
+        let (_, cands) = Completer::new(&env).complete("gc", 2);
+        assert_eq!(cands[0].display, "gcd(a, b)");
+        let (_, cands) = Completer::new(&env).complete("ste", 3);
+        assert_eq!(cands[0].display, "step(range, n)");
+    }
+
+    #[test]
+    fn completes_commands_and_settings() {
+        let env = Env::new();
+        assert_eq!(texts(&env, ":t"), (1, vec!["tokens ".to_string(), "tree ".to_string(), "time ".to_string()]));
+        assert_eq!(texts(&env, ":r").1, ["read "]);
+        assert_eq!(texts(&env, ":set m").1, ["mode "]);
+        assert!(texts(&env, ":load s").1.is_empty());
+    }
+
+    #[test]
+    fn leaves_numbers_alone() {
+        let env = Env::new();
+        assert!(texts(&env, "1e").1.is_empty());
+        assert!(texts(&env, "2pi").1.is_empty());
+        assert_eq!(texts(&env, "finance.n").0, 0);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+
+use crate::eval::EvalError;
+use crate::node::Value;
+
+/// `re + im i`, in floating point.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    /// Numbers of any kind as complex ones, or `None`.
+    pub fn from_value(val: &Value) -> Option<Self> {
+        match val {
+            Value::Num(n) => Some(Self::new(*n as f64, 0.0)),
+            Value::Float(x) => Some(Self::new(*x, 0.0)),
+            Value::Complex(z) => Some(*z),
+            _ => None,
+        }
+    }
+    /// A float when the imaginary part is zero, so `i * i` is `-1.0`.
+    pub fn value(self) -> Value {
+        match self.im == 0.0 {
+            true => Value::Float(self.re),
+            false => Value::Complex(self),
+        }
+    }
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+    pub fn neg(self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+    pub fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+    pub fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+    pub fn mul(self, other: Self) -> Self {
+        Self::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+    pub fn div(self, other: Self) -> Result<Self, EvalError> {
+        let norm = other.re * other.re + other.im * other.im;
+        if norm == 0.0 {
+            return Err(EvalError::DivByZero)
+        }
+        let num = self.mul(other.conj());
+        Ok(Self::new(num.re / norm, num.im / norm))
+    }
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+    /// Principal square root, with the cut along the negative reals. Exact
+    /// for `sqrt(-1)`, which the polar form is not.
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        match self.im.is_sign_negative() {
+            true => Self::new(re, -im),
+            false => Self::new(re, im),
+        }
+    }
+    /// Whole powers by repeated squaring, which keeps `i ** 2` exact,
+    /// and square roots as `sqrt`. Others go through the principal
+    /// logarithm.
+    pub fn pow(self, exp: &Value) -> Result<Self, EvalError> {
+        let whole = match exp {
+            Value::Num(n) => Some(*n),
+            Value::Float(x) if x.fract() == 0.0 && x.abs() < 1e15 => Some(*x as i64),
+            _ => None,
+        };
+        if let Some(n) = whole {
+            let mut base = match n < 0 {
+                true => Self::new(1.0, 0.0).div(self)?,
+                false => self,
+            };
+            let (mut n, mut res) = (n.unsigned_abs(), Self::new(1.0, 0.0));
+            while n > 0 {
+                if n & 1 == 1 {
+                    res = res.mul(base)
+                }
+                base = base.mul(base);
+                n >>= 1
+            }
+            return Ok(res)
+        }
+        let exp = Self::from_value(exp).unwrap();
+        if exp == Self::new(0.5, 0.0) {
+            return Ok(self.sqrt())
+        }
+        if self == Self::new(0.0, 0.0) {
+            return Ok(self)
+        }
+        let ln = Self::new(self.abs().ln(), self.arg());
+        let w = exp.mul(ln);
+        Ok(Self::from_polar(w.re.exp(), w.im))
+    }
+}
+
+/// `1+2i`, `0.5-1.5i` or `2i`, which read back as the same number.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.re == 0.0, self.im.is_sign_negative()) {
+            (true, _) => write!(f, "{}i", self.im),
+            (false, true) => write!(f, "{}-{}i", self.re, -self.im),
+            (false, false) => write!(f, "{}+{}i", self.re, self.im),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::eval::tests::run;
+    use crate::parser::tests::parse;
+    use crate::settings::{Form, Mode};
+
+    fn pow(z: Complex, exp: Value) -> Complex {
+        z.pow(&exp).ok().unwrap()
+    }
+
+    fn close(z: Complex, re: f64, im: f64) -> bool {
+        (z.re - re).abs() < 1e-12 && (z.im - im).abs() < 1e-12
+    }
+
+    #[test]
+    fn whole_powers_are_exact() {
+        assert!(pow(Complex::I, Value::Num(2)) == Complex::new(-1.0, 0.0));
+        assert!(pow(Complex::I, Value::Num(-1)) == Complex::new(0.0, -1.0));
+        assert!(pow(Complex::new(0.0, 2.0), Value::Float(3.0)) == Complex::new(0.0, -8.0));
+        assert!(pow(Complex::new(1.0, 1.0), Value::Num(0)) == Complex::new(1.0, 0.0));
+        assert!(matches!(Complex::new(0.0, 0.0).pow(&Value::Num(-1)), Err(EvalError::DivByZero)));
+    }
+
+    #[test]
+    fn square_roots_cut_along_the_negative_reals() {
+        assert!(Complex::new(-4.0, 0.0).sqrt() == Complex::new(0.0, 2.0));
+        assert!(Complex::new(-4.0, -0.0).sqrt() == Complex::new(0.0, -2.0));
+        assert!(pow(Complex::new(-1.0, 0.0), Value::Float(0.5)) == Complex::I);
+        assert!(pow(Complex::new(0.0, 0.0), Value::Float(0.5)) == Complex::new(0.0, 0.0));
+        assert!(close(Complex::I.sqrt(), 0.5f64.sqrt(), 0.5f64.sqrt()));
+    }
+
+    #[test]
+    fn other_powers_take_the_principal_branch() {
+        assert!(close(pow(Complex::new(-8.0, 0.0), Value::Float(1.0 / 3.0)), 1.0, 3f64.sqrt()));
+        assert!(close(pow(Complex::I, Value::Complex(Complex::I)), (-std::f64::consts::FRAC_PI_2).exp(), 0.0));
+        assert!(close(pow(Complex::new(0.0, 0.0), Value::Float(1.5)), 0.0, 0.0));
+        assert!(close(Complex::from_polar(2.0, std::f64::consts::PI), -2.0, 0.0));
+    }
+
+    #[test]
+    fn prints_as_it_reads() {
+        for (z, s) in [((1.0, 2.0), "1+2i"), ((0.5, -1.5), "0.5-1.5i"), ((0.0, 2.0), "2i"), ((0.0, -1.0), "-1i")] {
+            assert_eq!(Complex::new(z.0, z.1).to_string(), s);
+            assert_eq!(parse(s).eval(&Env::new()).ok().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn follows_the_mode_and_form() {
+        let mut env = Env::new();
+        for (src, res) in [("i * i", "-1.0"), ("(1+2i) * (3-1i)", "5+5i"), ("(1+2i) / (1-1i)", "-0.5+1.5i"), ("abs(3+4i)", "5.0"),
+                           ("conj(1+2i)", "1-2i"), ("sqrt(-4)", "NaN"), ("(1+2i) < 1", "error: not supported: Lt on 1+2i and 1"),
+                           ("1 / (0i)", "error: division by zero")] {
+            assert_eq!(run(&mut env, src), res, "{}", src);
+        }
+        env.settings.mode = Mode::Complex;
+        assert_eq!(run(&mut env, "sqrt(-4)"), "2i");
+        assert_eq!(run(&mut env, "(-4) ** 0.5"), "2i");
+        env.settings.form = Form::Polar;
+        env.settings.precision = Some(3);
+        let val = parse("2+2i").eval(&env).ok().unwrap();
+        assert_eq!(env.settings.show(&val).to_string(), "2.828∠0.785");
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+use crate::env::Env;
+use crate::node::{Node, Value, BinaryOp};
+
+/// Graphviz source for a tree. Operators and leaves become labelled nodes;
+/// with `env`, every subtree that evaluates is annotated with its value.
+pub fn dot(node: &Node, env: Option<&Env>) -> String {
+    let mut lines = Vec::new();
+    write_node(&mut lines, node, env, &mut 0);
+    let mut out = String::new();
+    out.push_str("digraph expr {\n");
+    out.push_str("    node [fontname=\"monospace\"];\n");
+    for line in lines {
+        writeln!(out, "    {}", line).unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', r"\\").replace('"', r#"\""#)
+}
+
+/// Value of `node` given the values of its children, so each subtree is
+/// evaluated once however deep the tree is. Branches and the right side of
+/// `&&` and `||` only count when taken.
+fn value_of(node: &Node, vals: Vec<Option<Value>>, env: &Env) -> Option<Value> {
+    match node {
+        Node::If(..) => {
+            let mut vals = vals.into_iter();
+            let cond = vals.next()??.truthy().ok()?;
+            let (then, other) = (vals.next()?, vals.next()?);
+            return if cond { then } else { other }
+        }
+        Node::Binary(op @ (BinaryOp::LogicAnd | BinaryOp::LogicOr), ..) => {
+            let left = vals[0].as_ref()?.truthy().ok()?;
+            let res = match (op, left) {
+                (BinaryOp::LogicAnd, false) => false,
+                (BinaryOp::LogicOr, true) => true,
+                _ => vals[1].as_ref()?.truthy().ok()?,
+            };
+            return Some(Value::Num(res as i64))
+        }
+        Node::For(var, _, body) => {
+            let seq = Node::Val(vals.into_iter().next()??);
+            return Node::For(var.clone(), Box::new(seq), body.clone()).eval(env).ok()
+        }
+        // the body only runs when the function is called
+        Node::Lambda(..) => return node.eval(env).ok(),
+        _ => {}
+    }
+    let mut vals = vals.into_iter().map(|val| val.map(Node::Val)).collect::<Option<Vec<_>>>()?.into_iter();
+    let mut next = || Box::new(vals.next().unwrap());
+    let node = match node {
+        Node::Val(val) => return Some(val.clone()),
+        Node::Var(..) => node.clone(),
+        Node::Unary(op, ..) => Node::Unary(op.clone(), next()),
+        Node::Binary(op, ..) => Node::Binary(op.clone(), next(), next()),
+        Node::Call(name, args) => Node::Call(name.clone(), args.iter().map(|_| *next()).collect()),
+        Node::List(items) => Node::List(items.iter().map(|_| *next()).collect()),
+        Node::Index(..) => Node::Index(next(), next()),
+        Node::If(..) | Node::For(..) | Node::Lambda(..) => unreachable!(),
+    };
+    node.eval(env).ok()
+}
+
+/// Writes the statements for `node` and its subtrees, returning its id and,
+/// with `env`, its value.
+fn write_node(lines: &mut Vec<String>, node: &Node, env: Option<&Env>, next_id: &mut usize) -> (usize, Option<Value>) {
+    let id = *next_id;
+    *next_id += 1;
+    let line = lines.len();
+    lines.push(String::new());
+
+    let children: Vec<&Node> = match node {
+        Node::Val(..) | Node::Var(..) => Vec::new(),
+        Node::Unary(_, node) => vec![node],
+        Node::Binary(_, left, right) => vec![left, right],
+        Node::Call(_, args) => args.iter().collect(),
+        Node::If(cond, then, other) => vec![cond, then, other],
+        Node::For(_, seq, body) => vec![seq, body],
+        Node::List(items) => items.iter().collect(),
+        Node::Index(node, index) => vec![node, index],
+        Node::Lambda(_, body) => vec![body],
+    };
+    let mut vals = Vec::new();
+    for (i, child) in children.into_iter().enumerate() {
+        // loop and function bodies take new values on every run, so they
+        // stay bare
+        let body = matches!(node, Node::For(..)) && i == 1 || matches!(node, Node::Lambda(..));
+        let env = if body { None } else { env };
+        let (child_id, val) = write_node(lines, child, env, next_id);
+        lines.push(format!("n{} -> n{};", id, child_id));
+        vals.push(val);
+    }
+    let val = env.and_then(|env| value_of(node, vals, env));
+
+    let (label, shape) = match node {
+        Node::Val(val) => (val.to_string(), "box"),
+        Node::Var(name) => (name.clone(), "box"),
+        Node::Unary(op, ..) => (op.to_string(), "ellipse"),
+        Node::Binary(op, ..) => (op.to_string(), "ellipse"),
+        Node::Call(name, ..) => (format!("{}()", name), "ellipse"),
+        Node::If(..) => (format!("if"), "diamond"),
+        Node::For(var, ..) => (format!("for {}", var), "ellipse"),
+        Node::List(..) => (format!("[]"), "ellipse"),
+        Node::Index(..) => (format!("index"), "ellipse"),
+        Node::Lambda(params, ..) => (format!("|{}|", params.join(", ")), "ellipse"),
+    };
+    let label = match &val {
+        Some(val) if !matches!(node, Node::Val(..)) => format!("{}\\n= {}", escape(&label), escape(&val.to_string())),
+        _ => escape(&label),
+    };
+    lines[line] = format!("n{} [label=\"{}\", shape={}];", id, label, shape);
+    (id, val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+
+    #[test]
+    fn links_children_in_order() {
+        assert_eq!(dot(&parse("-x"), None), concat!(
+            "digraph expr {\n",
+            "    node [fontname=\"monospace\"];\n",
+            "    n0 [label=\"Neg\", shape=ellipse];\n",
+            "    n1 [label=\"x\", shape=box];\n",
+            "    n0 -> n1;\n",
+            "}\n",
+        ));
+        let src = dot(&parse("if a then f(b, c) else d"), None);
+        assert!(src.contains("n0 [label=\"if\", shape=diamond];"));
+        assert!(src.contains("n2 [label=\"f()\", shape=ellipse];"));
+        let edges = src.lines().filter(|line| line.contains("->")).collect::<Vec<_>>();
+        assert_eq!(edges, ["    n0 -> n1;", "    n2 -> n3;", "    n2 -> n4;", "    n0 -> n2;", "    n0 -> n5;"]);
+    }
+
+    #[test]
+    fn annotates_values_with_an_env() {
+        let mut env = Env::new();
+        env.bind("x", Value::Num(3));
+        let src = dot(&parse("x * (y + 1)"), Some(&env));
+        assert!(src.contains("n1 [label=\"x\\n= 3\", shape=box];"));
+        // subtrees that do not evaluate keep a bare label
+        assert!(src.contains("n0 [label=\"Mul\", shape=ellipse];"));
+        assert!(src.contains("n4 [label=\"1\", shape=box];"));
+    }
+
+    #[test]
+    fn reuses_child_values() {
+        let mut env = Env::new();
+        env.bind("x", Value::Num(1));
+        let node = (1..300).fold(parse("x"), |node, _| Node::Binary(BinaryOp::Add, Box::new(node), Box::new(parse("x"))));
+        assert!(dot(&node, Some(&env)).contains("n0 [label=\"Add\\n= 300\", shape=ellipse];"));
+        let src = dot(&parse("max(x, 2) * -x"), Some(&env));
+        assert!(src.contains("n0 [label=\"Mul\\n= -2\", shape=ellipse];"));
+        assert!(src.contains("n1 [label=\"max()\\n= 2\", shape=ellipse];"));
+        // only the branch taken decides
+        let src = dot(&parse("x > 0 ? x * 2 : 0 && y || x"), Some(&env));
+        assert!(src.contains("n0 [label=\"if\\n= 2\", shape=diamond];"));
+        assert!(src.contains("n7 [label=\"LogicOr\\n= 1\", shape=ellipse];"));
+        assert!(src.contains("n8 [label=\"LogicAnd\\n= 0\", shape=ellipse];"));
+        assert!(src.contains("n10 [label=\"y\", shape=box];"));
+        let src = dot(&parse("x < 0 ? 1 : y"), Some(&env));
+        assert!(src.contains("n0 [label=\"if\", shape=diamond];"));
+        let src = dot(&parse("sum(x * 2 for x in 1..=3)"), Some(&env));
+        assert!(src.contains("n0 [label=\"sum()\\n= 12\", shape=ellipse];"));
+        assert!(src.contains("n2 [label=\"RangeIncl\\n= 1..=3\", shape=ellipse];"));
+        assert!(src.contains("n1 [label=\"for x\\n= [2, 4, 6]\", shape=ellipse];"));
+        assert!(src.contains("n5 [label=\"Mul\", shape=ellipse];"));
+        let src = dot(&parse("[x, 2][1] + x"), Some(&env));
+        assert!(src.contains("n1 [label=\"index\\n= 2\", shape=ellipse];"));
+        assert!(src.contains("n2 [label=\"[]\\n= [1, 2]\", shape=ellipse];"));
+        let src = dot(&parse("map([x], |y| y + x)"), Some(&env));
+        assert!(src.contains("n0 [label=\"map()\\n= [2]\", shape=ellipse];"));
+        assert!(src.contains("n4 [label=\"Add\", shape=ellipse];"));
+        assert!(src.contains("n6 [label=\"x\", shape=box];"));
+    }
+
+    #[test]
+    fn escapes_labels() {
+        let node = Node::Val(Value::Str(r#"a\"b"#.to_string()));
+        assert!(dot(&node, None).contains(r#"label="\"a\\\"b\"""#));
+    }
+}
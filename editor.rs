@@ -0,0 +1,798 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, stdin, stdout, IsTerminal, Write};
+use std::path::PathBuf;
+
+const MAX_HISTORY: usize = 1000;
+
+#[derive(Clone, Copy)]
+pub enum Keymap {
+    Emacs,
+    Vi,
+}
+
+impl Keymap {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "emacs" => Some(Self::Emacs),
+            "vi" => Some(Self::Vi),
+            _ => None,
+        }
+    }
+}
+
+pub enum Input {
+    Line(String),
+    Cancel,
+    Eof,
+}
+
+/// A completion offered on Tab: `text` replaces the word before the
+/// cursor, `display` is what the candidate list shows.
+pub struct Candidate {
+    pub text: String,
+    pub display: String
+}
+
+/// Hooks through which the REPL tells the editor about its language.
+pub trait Helper {
+    /// Start of the word being completed at byte offset `pos`, and its
+    /// candidates.
+    fn complete(&self, line: &str, pos: usize) -> (usize, Vec<Candidate>);
+    /// `line` with ANSI colours, the cursor at char offset `pos`. Must not
+    /// change the visible width.
+    fn highlight(&self, line: &str, _pos: usize) -> String {
+        line.to_string()
+    }
+    /// Text shown dimmed after the line while typing.
+    fn hint(&self, _line: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Line editor for the REPL with history kept under the user's data
+/// directory. Falls back to plain line reads when stdin is not a terminal.
+pub struct Editor {
+    keymap: Keymap,
+    history: Vec<String>,
+    path: Option<PathBuf>,
+    /// Lines in the history file, which is rewritten once it holds more
+    /// than `MAX_HISTORY`.
+    saved: usize
+}
+
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Esc,
+
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+
+    Unknown,
+}
+
+struct Line {
+    buf: Vec<char>,
+    pos: usize
+}
+
+/// Raw terminal access through libc. The termios layout, flag values and
+/// ioctl numbers are the generic Linux ones, which mips, powerpc and sparc
+/// do not use.
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64", target_arch = "arm", target_arch = "riscv64")))]
+mod sys {
+    use std::os::raw::{c_int, c_uint, c_ulong};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub iflag: c_uint,
+        pub oflag: c_uint,
+        pub cflag: c_uint,
+        pub lflag: c_uint,
+        pub line: u8,
+        pub cc: [u8; 32],
+        pub ispeed: c_uint,
+        pub ospeed: c_uint
+    }
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: i16,
+        revents: i16
+    }
+
+    extern "C" {
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+        fn read(fd: c_int, buf: *mut u8, count: usize) -> isize;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    #[repr(C)]
+    struct WinSize {
+        rows: u16,
+        cols: u16,
+        xpixel: u16,
+        ypixel: u16
+    }
+
+    const TIOCGWINSZ: c_ulong = 0x5413;
+
+    const ICRNL: c_uint = 0o400;
+    const IXON: c_uint = 0o2000;
+    const ISIG: c_uint = 0o1;
+    const ICANON: c_uint = 0o2;
+    const ECHO: c_uint = 0o10;
+    const IEXTEN: c_uint = 0o100000;
+    const VTIME: usize = 5;
+    const VMIN: usize = 6;
+    const TCSAFLUSH: c_int = 2;
+
+    /// Puts the terminal in raw mode until dropped.
+    pub struct RawMode {
+        orig: Termios
+    }
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            let mut orig = Termios {
+                iflag: 0, oflag: 0, cflag: 0, lflag: 0, line: 0, cc: [0; 32], ispeed: 0, ospeed: 0
+            };
+            if unsafe { tcgetattr(0, &mut orig) } != 0 {
+                return None
+            }
+            let mut raw = orig;
+            raw.iflag &= !(ICRNL | IXON);
+            raw.lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+            raw.cc[VMIN] = 1;
+            raw.cc[VTIME] = 0;
+            if unsafe { tcsetattr(0, TCSAFLUSH, &raw) } != 0 {
+                return None
+            }
+            Some(Self { orig })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe { tcsetattr(0, TCSAFLUSH, &self.orig) };
+        }
+    }
+
+    pub fn read_byte() -> Option<u8> {
+        let mut byte = 0u8;
+        match unsafe { read(0, &mut byte, 1) } {
+            1 => Some(byte),
+            _ => None,
+        }
+    }
+
+    /// Whether another byte arrives within `millis`, to tell a lone escape
+    /// from the start of an escape sequence.
+    pub fn pending(millis: i32) -> bool {
+        let mut fd = PollFd { fd: 0, events: 1, revents: 0 };
+        unsafe { poll(&mut fd, 1, millis) > 0 }
+    }
+
+    pub fn width() -> usize {
+        let mut size = WinSize { rows: 0, cols: 0, xpixel: 0, ypixel: 0 };
+        match unsafe { ioctl(1, TIOCGWINSZ, &mut size) } {
+            0 if size.cols > 0 => size.cols as usize,
+            _ => 80,
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64", target_arch = "arm", target_arch = "riscv64"))))]
+mod sys {
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            None
+        }
+    }
+
+    pub fn read_byte() -> Option<u8> {
+        None
+    }
+
+    pub fn pending(_millis: i32) -> bool {
+        false
+    }
+
+    pub fn width() -> usize {
+        80
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(dir.join("calc").join("history"))
+}
+
+fn read_key() -> Option<Key> {
+    key_from(&mut sys::read_byte, || sys::pending(30))
+}
+
+/// Decode one key from `next` bytes. `pending` tells whether more input
+/// follows an escape.
+fn key_from(next: &mut dyn FnMut() -> Option<u8>, pending: impl Fn() -> bool) -> Option<Key> {
+    let byte = next()?;
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        27 => {
+            if !pending() {
+                return Some(Key::Esc)
+            }
+            match next()? {
+                b'O' => match next()? {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    b'C' => Key::Right,
+                    b'D' => Key::Left,
+                    b'H' => Key::Home,
+                    b'F' => Key::End,
+                    _ => Key::Unknown,
+                },
+                b'[' => {
+                    // parameter and intermediate bytes, up to the final byte
+                    let mut code = Vec::new();
+                    let last = loop {
+                        match next()? {
+                            c @ 0x20..=0x3f => code.push(c),
+                            c => break c,
+                        }
+                    };
+                    match (code.as_slice(), last) {
+                        (b"", b'A') => Key::Up,
+                        (b"", b'B') => Key::Down,
+                        (b"", b'C') => Key::Right,
+                        (b"", b'D') => Key::Left,
+                        (b"", b'H') => Key::Home,
+                        (b"", b'F') => Key::End,
+                        (b"1" | b"7", b'~') => Key::Home,
+                        (b"4" | b"8", b'~') => Key::End,
+                        (b"3", b'~') => Key::Delete,
+                        _ => Key::Unknown,
+                    }
+                }
+                c => Key::Alt(c as char),
+            }
+        }
+        1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        byte if byte < 0x80 => Key::Char(byte as char),
+        byte => {
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(next()?);
+            }
+            match String::from_utf8(bytes) {
+                Ok(s) => Key::Char(s.chars().next()?),
+                Err(..) => Key::Unknown,
+            }
+        }
+    };
+    Some(key)
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            pos: 0
+        }
+    }
+    fn set(&mut self, s: &str) {
+        self.buf = s.chars().collect();
+        self.pos = self.buf.len();
+    }
+    fn text(&self) -> String {
+        self.buf.iter().collect()
+    }
+    fn insert(&mut self, c: char) {
+        self.buf.insert(self.pos, c);
+        self.pos += 1;
+    }
+    fn backspace(&mut self) {
+        if self.pos > 0 {
+            self.pos -= 1;
+            self.buf.remove(self.pos);
+        }
+    }
+    fn delete(&mut self) {
+        if self.pos < self.buf.len() {
+            self.buf.remove(self.pos);
+        }
+    }
+    fn word_left(&self) -> usize {
+        let mut pos = self.pos;
+        while pos > 0 && !self.buf[pos - 1].is_alphanumeric() {
+            pos -= 1
+        }
+        while pos > 0 && self.buf[pos - 1].is_alphanumeric() {
+            pos -= 1
+        }
+        pos
+    }
+    fn word_right(&self) -> usize {
+        let mut pos = self.pos;
+        while pos < self.buf.len() && !self.buf[pos].is_alphanumeric() {
+            pos += 1
+        }
+        while pos < self.buf.len() && self.buf[pos].is_alphanumeric() {
+            pos += 1
+        }
+        pos
+    }
+}
+
+impl Editor {
+    pub fn new(keymap: Keymap) -> Self {
+        let path = history_path();
+        let history = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|src| src.lines().map(String::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let saved = history.len();
+        let skip = history.len().saturating_sub(MAX_HISTORY);
+        Self {
+            keymap,
+            history: history.into_iter().skip(skip).collect(),
+            path,
+            saved
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str, helper: &dyn Helper) -> io::Result<Input> {
+        if !stdin().is_terminal() {
+            return read_plain(prompt)
+        }
+        let raw = match sys::RawMode::enable() {
+            Some(raw) => raw,
+            None => return read_plain(prompt),
+        };
+        let input = self.edit(prompt, helper);
+        drop(raw);
+        println!();
+        if let Input::Line(line) = &input {
+            self.add_history(line);
+        }
+        Ok(input)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if self.saved >= MAX_HISTORY {
+            let mut src = self.history.join("\n");
+            src.push('\n');
+            if fs::write(path, src).is_ok() {
+                self.saved = self.history.len();
+            }
+        }
+        else if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+            if writeln!(file, "{}", line).is_ok() {
+                self.saved += 1;
+            }
+        }
+    }
+
+    /// Redraw the line, with the helper's hint unless the line is done.
+    fn refresh(&self, prompt: &str, line: &Line, helper: &dyn Helper, done: bool) {
+        let text = line.text();
+        let width = prompt.chars().count() + line.buf.len();
+        let col = prompt.chars().count() + line.pos;
+        print!("\r{}{}", prompt, helper.highlight(&text, line.pos));
+        if let (Some(hint), false) = (helper.hint(&text), done) {
+            let room = sys::width().saturating_sub(width + 3);
+            let hint = hint.chars().take(room).collect::<String>();
+            print!("  \x1b[2m{}\x1b[0m", hint)
+        }
+        print!("\x1b[K\r");
+        if col > 0 {
+            print!("\x1b[{}C", col)
+        }
+        let _ = stdout().flush();
+    }
+
+    fn edit(&mut self, prompt: &str, helper: &dyn Helper) -> Input {
+        let mut line = Line::new();
+        let mut index = self.history.len();
+        let mut edited = String::new();
+        let mut normal = false;
+        let mut pending_d = false;
+        let mut listed = false;
+        loop {
+            self.refresh(prompt, &line, helper, false);
+            let key = match read_key() {
+                Some(key) => key,
+                None => return Input::Eof,
+            };
+            if let Key::Tab = key {
+                listed = self.complete(&mut line, helper, listed);
+                continue;
+            }
+            listed = false;
+            if let (Keymap::Vi, true) = (self.keymap, normal) {
+                let was_d = pending_d;
+                pending_d = false;
+                match key {
+                    Key::Char('h') | Key::Left => line.pos = line.pos.saturating_sub(1),
+                    Key::Char('l') | Key::Right => line.pos = (line.pos + 1).min(line.buf.len()),
+                    Key::Char('0') | Key::Home => line.pos = 0,
+                    Key::Char('$') | Key::End => line.pos = line.buf.len(),
+                    Key::Char('b') => line.pos = line.word_left(),
+                    Key::Char('w') => line.pos = line.word_right(),
+                    Key::Char('x') => line.delete(),
+                    Key::Char('D') => line.buf.truncate(line.pos),
+                    Key::Char('d') if was_d => line.set(""),
+                    Key::Char('d') => pending_d = true,
+                    Key::Char('i') => normal = false,
+                    Key::Char('a') => {
+                        line.pos = (line.pos + 1).min(line.buf.len());
+                        normal = false
+                    }
+                    Key::Char('I') => {
+                        line.pos = 0;
+                        normal = false
+                    }
+                    Key::Char('A') => {
+                        line.pos = line.buf.len();
+                        normal = false
+                    }
+                    Key::Char('k') | Key::Up => self.step_history(&mut line, &mut index, &mut edited, -1),
+                    Key::Char('j') | Key::Down => self.step_history(&mut line, &mut index, &mut edited, 1),
+                    Key::Char('/') | Key::Ctrl('r') => {
+                        if let Some(input) = self.search(prompt, &mut line, helper) {
+                            return input
+                        }
+                    }
+                    Key::Enter | Key::Ctrl('c') => return self.finish(prompt, &line, helper, key),
+                    Key::Ctrl('d') if line.buf.is_empty() => return Input::Eof,
+                    _ => {}
+                }
+                continue;
+            }
+            match key {
+                Key::Enter | Key::Ctrl('c') => return self.finish(prompt, &line, helper, key),
+                Key::Ctrl('d') if line.buf.is_empty() => return Input::Eof,
+                Key::Ctrl('d') | Key::Delete => line.delete(),
+                Key::Backspace | Key::Ctrl('h') => line.backspace(),
+                Key::Esc if let Keymap::Vi = self.keymap => {
+                    normal = true;
+                    line.pos = line.pos.saturating_sub(1)
+                }
+
+                Key::Left | Key::Ctrl('b') => line.pos = line.pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => line.pos = (line.pos + 1).min(line.buf.len()),
+                Key::Home | Key::Ctrl('a') => line.pos = 0,
+                Key::End | Key::Ctrl('e') => line.pos = line.buf.len(),
+                Key::Alt('b') => line.pos = line.word_left(),
+                Key::Alt('f') => line.pos = line.word_right(),
+
+                Key::Ctrl('k') => line.buf.truncate(line.pos),
+                Key::Ctrl('u') => {
+                    line.buf.drain(..line.pos);
+                    line.pos = 0
+                }
+                Key::Ctrl('w') => {
+                    let start = line.word_left();
+                    line.buf.drain(start..line.pos);
+                    line.pos = start
+                }
+                Key::Ctrl('l') => print!("\x1b[H\x1b[2J"),
+
+                Key::Up | Key::Ctrl('p') => self.step_history(&mut line, &mut index, &mut edited, -1),
+                Key::Down | Key::Ctrl('n') => self.step_history(&mut line, &mut index, &mut edited, 1),
+                Key::Ctrl('r') => {
+                    if let Some(input) = self.search(prompt, &mut line, helper) {
+                        return input
+                    }
+                }
+
+                Key::Char(c) => line.insert(c),
+                _ => {}
+            }
+        }
+    }
+
+    /// Redraw the line without its hint and end the read.
+    fn finish(&self, prompt: &str, line: &Line, helper: &dyn Helper, key: Key) -> Input {
+        self.refresh(prompt, line, helper, true);
+        match key {
+            Key::Enter => Input::Line(line.text()),
+            _ => Input::Cancel,
+        }
+    }
+
+    /// Complete the word before the cursor. With several candidates it
+    /// inserts their common prefix, or lists them when `listed` is false and
+    /// there is nothing to insert. Returns whether the list is on screen.
+    fn complete(&self, line: &mut Line, helper: &dyn Helper, listed: bool) -> bool {
+        let text = line.text();
+        let pos = text
+            .char_indices()
+            .nth(line.pos)
+            .map_or(text.len(), |(index, _)| index);
+        let (start, cands) = helper.complete(&text, pos);
+        let word = &text[start..pos];
+        let prefix = match cands.split_first() {
+            None => return listed,
+            Some((first, rest)) => rest.iter().fold(first.text.as_str(), |prefix, cand| {
+                let len = prefix
+                    .char_indices()
+                    .zip(cand.text.chars())
+                    .take_while(|((_, a), b)| a == b)
+                    .last()
+                    .map_or(0, |((index, c), _)| index + c.len_utf8());
+                &prefix[..len]
+            }),
+        };
+        if prefix.len() > word.len() {
+            let start = text[..start].chars().count();
+            line.buf.splice(start..line.pos, prefix.chars());
+            line.pos = start + prefix.chars().count();
+            return false
+        }
+        if cands.len() > 1 && !listed {
+            let width = cands
+                .iter()
+                .map(|cand| cand.display.chars().count())
+                .max()
+                .unwrap_or(0) + 2;
+            let columns = (80 / width).max(1);
+            print!("\r\n");
+            for (index, cand) in cands.iter().enumerate() {
+                print!("{:width$}", cand.display, width = width);
+                if index % columns == columns - 1 || index == cands.len() - 1 {
+                    print!("\r\n")
+                }
+            }
+            return true
+        }
+        listed
+    }
+
+    fn step_history(&self, line: &mut Line, index: &mut usize, edited: &mut String, step: isize) {
+        let next = *index as isize + step;
+        if next < 0 || next > self.history.len() as isize {
+            return
+        }
+        if *index == self.history.len() {
+            *edited = line.text()
+        }
+        *index = next as usize;
+        match self.history.get(*index) {
+            Some(entry) => line.set(entry),
+            None => line.set(edited),
+        }
+    }
+
+    /// Reverse incremental search. Returns the input to finish the read
+    /// with, or leaves the match in `line` for further editing.
+    fn search(&self, prompt: &str, line: &mut Line, helper: &dyn Helper) -> Option<Input> {
+        let orig = line.text();
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        loop {
+            let shown = found.map_or("", |index| self.history[index].as_str());
+            print!("\r(reverse-i-search)`{}': {}\x1b[K", query, shown);
+            let _ = stdout().flush();
+            let from = match read_key()? {
+                Key::Char(c) => {
+                    query.push(c);
+                    found.map_or(self.history.len(), |index| index + 1)
+                }
+                Key::Backspace => {
+                    query.pop();
+                    self.history.len()
+                }
+                Key::Ctrl('r') => found.unwrap_or(self.history.len()),
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    line.set(&orig);
+                    self.refresh(prompt, line, helper, false);
+                    return None
+                }
+                Key::Enter => return Some(Input::Line(shown.to_string())),
+                _ => {
+                    line.set(shown);
+                    return None
+                }
+            };
+            found = self.history[..from.min(self.history.len())]
+                .iter()
+                .rposition(|entry| entry.contains(&query))
+                .or(found.filter(|&index| self.history[index].contains(&query)));
+            print!("\r\x1b[K");
+        }
+    }
+}
+
+fn read_plain(prompt: &str) -> io::Result<Input> {
+    print!("{}", prompt);
+    stdout().flush()?;
+    let mut line = String::new();
+    if stdin().read_line(&mut line)? == 0 {
+        return Ok(Input::Eof)
+    }
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    Ok(Input::Line(line.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(history: &[&str]) -> Editor {
+        Editor {
+            keymap: Keymap::Emacs,
+            history: history.iter().map(|entry| entry.to_string()).collect(),
+            path: None,
+            saved: 0
+        }
+    }
+
+    fn key(bytes: &[u8]) -> (Option<Key>, usize) {
+        let mut bytes = bytes.iter().copied();
+        let key = key_from(&mut || bytes.next(), || true);
+        (key, bytes.count())
+    }
+
+    fn line(text: &str, pos: usize) -> Line {
+        let mut line = Line::new();
+        line.set(text);
+        line.pos = pos;
+        line
+    }
+
+    struct Words(&'static [&'static str]);
+
+    impl Helper for Words {
+        fn complete(&self, line: &str, pos: usize) -> (usize, Vec<Candidate>) {
+            let start = line[..pos].rfind(' ').map_or(0, |index| index + 1);
+            let cands = self.0
+                .iter()
+                .filter(|word| word.starts_with(&line[start..pos]))
+                .map(|word| Candidate { text: word.to_string(), display: word.to_string() })
+                .collect();
+            (start, cands)
+        }
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut line = line("sqt(2)", 2);
+        line.insert('r');
+        assert_eq!((line.text(), line.pos), ("sqrt(2)".to_string(), 3));
+        line.backspace();
+        line.delete();
+        assert_eq!((line.text(), line.pos), ("sq(2)".to_string(), 2));
+        let mut start = self::line("", 0);
+        start.backspace();
+        start.delete();
+        assert_eq!(start.text(), "");
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let line = line("foo + bar2(x)", 8);
+        assert_eq!(line.word_left(), 6);
+        assert_eq!(line.word_right(), 10);
+        assert_eq!(self::line("a + b", 0).word_left(), 0);
+        assert_eq!(self::line("a + b", 5).word_right(), 5);
+    }
+
+    #[test]
+    fn keeps_history_without_repeats() {
+        let mut editor = editor(&[]);
+        for entry in ["1", "1", " ", "2", "1"] {
+            editor.add_history(entry)
+        }
+        assert_eq!(editor.history, ["1", "2", "1"]);
+        for i in 0..MAX_HISTORY + 5 {
+            editor.add_history(&i.to_string())
+        }
+        assert_eq!(editor.history.len(), MAX_HISTORY);
+        assert_eq!(editor.history[0], "5");
+    }
+
+    #[test]
+    fn steps_through_history_back_to_the_edited_line() {
+        let editor = editor(&["a", "b"]);
+        let (mut line, mut index, mut edited) = (line("draft", 5), 2, String::new());
+        editor.step_history(&mut line, &mut index, &mut edited, -1);
+        assert_eq!(line.text(), "b");
+        editor.step_history(&mut line, &mut index, &mut edited, -1);
+        editor.step_history(&mut line, &mut index, &mut edited, -1);
+        assert_eq!((line.text(), index), ("a".to_string(), 0));
+        editor.step_history(&mut line, &mut index, &mut edited, 1);
+        editor.step_history(&mut line, &mut index, &mut edited, 1);
+        assert_eq!((line.text(), index), ("draft".to_string(), 2));
+    }
+
+    #[test]
+    fn trims_the_history_file() {
+        let path = env::temp_dir().join(format!("calc-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut editor = Editor { path: Some(path.clone()), ..editor(&[]) };
+        for i in 0..MAX_HISTORY + 5 {
+            editor.add_history(&i.to_string())
+        }
+        let src = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines = src.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), MAX_HISTORY);
+        assert_eq!((lines[0], lines[MAX_HISTORY - 1]), ("5", "1004"));
+    }
+
+    #[test]
+    fn reads_escape_sequences_to_their_final_byte() {
+        assert!(matches!(key(b"\x1b[A"), (Some(Key::Up), 0)));
+        assert!(matches!(key(b"\x1bOH"), (Some(Key::Home), 0)));
+        assert!(matches!(key(b"\x1b[3~x"), (Some(Key::Delete), 1)));
+        // modified keys end at their final byte and leave the input alone
+        for seq in [&b"\x1b[1;5Cx"[..], b"\x1b[1;2Px", b"\x1b[2;2~x", b"\x1b[200~x"] {
+            assert!(matches!(key(seq), (Some(Key::Unknown), 1)), "{:?}", seq);
+        }
+        assert!(matches!(key(b"\x1bb"), (Some(Key::Alt('b')), 0)));
+        assert!(matches!(key("é".as_bytes()), (Some(Key::Char('é')), 0)));
+    }
+
+    #[test]
+    fn completes_the_common_prefix() {
+        let editor = editor(&[]);
+        let words = Words(&["sqrt", "sum", "solve"]);
+        let mut text = line("1 + sq", 6);
+        assert!(!editor.complete(&mut text, &words, false));
+        assert_eq!((text.text(), text.pos), ("1 + sqrt".to_string(), 8));
+        // nothing to insert: the list goes on screen once
+        let mut text = line("s", 1);
+        assert!(editor.complete(&mut text, &words, false));
+        assert!(editor.complete(&mut text, &words, true));
+        assert_eq!(text.text(), "s");
+    }
+
+    #[test]
+    fn names_keymaps() {
+        assert!(matches!(Keymap::from_name("vi"), Some(Keymap::Vi)));
+        assert!(Keymap::from_name("ed").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use crate::builtin;
+use crate::eval::{EvalError, MAX_ITERATIONS};
+use crate::module::Modules;
+use crate::node::{Node, Value};
+use crate::settings::Settings;
+
+/// A function defined with `f(x, y) = body`.
+#[derive(Clone)]
+pub struct Func {
+    pub params: Vec<String>,
+    pub body: Node
+}
+
+#[derive(Clone)]
+pub struct Env {
+    vars: HashMap<String, Value>,
+    funcs: HashMap<String, Func>,
+    results: Vec<Value>,
+    /// Loop iterations of the current evaluation.
+    steps: Cell<usize>,
+    pub settings: Settings,
+    pub modules: Modules
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self {
+            vars: HashMap::new(),
+            funcs: HashMap::new(),
+            results: Vec::new(),
+            steps: Cell::new(0),
+            settings: Settings::new(),
+            modules: Modules::new()
+        }
+    }
+    pub fn bind(&mut self, name: &str, val: Value) {
+        self.vars.insert(name.to_string(), val);
+    }
+    /// Variables first, then the results `ans` and `_1`, `_2`, ..., then
+    /// the builtin constants.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars
+            .get(name)
+            .or_else(|| self.result(name))
+            .or_else(|| builtin::constant(name))
+    }
+    fn result(&self, name: &str) -> Option<&Value> {
+        if name == "ans" {
+            return self.results.last()
+        }
+        let index = name
+            .strip_prefix('_')?
+            .parse::<usize>()
+            .ok()?;
+        self.results.get(index.checked_sub(1)?)
+    }
+    /// Record the result of a line, which becomes `ans` and `_N` with `N`
+    /// the new `result_count`.
+    pub fn push_result(&mut self, val: Value) {
+        self.results.push(val);
+    }
+    pub fn result_count(&self) -> usize {
+        self.results.len()
+    }
+    /// A variable bound in this environment, ignoring results and constants.
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+    /// Count an iteration, failing past `MAX_ITERATIONS` so a runaway loop
+    /// cannot hang the prompt.
+    pub fn step(&self) -> Result<(), EvalError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        match steps > MAX_ITERATIONS {
+            true => Err(EvalError::TooLong),
+            false => Ok(()),
+        }
+    }
+    pub fn reset_steps(&self) {
+        self.steps.set(0)
+    }
+    pub fn define(&mut self, name: &str, func: Func) {
+        self.funcs.insert(name.to_string(), func);
+    }
+    pub fn func(&self, name: &str) -> Option<&Func> {
+        self.funcs.get(name)
+    }
+    /// Variables sorted by name.
+    pub fn vars(&self) -> Vec<(&String, &Value)> {
+        let mut vars = self.vars.iter().collect::<Vec<_>>();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+    /// User functions sorted by name.
+    pub fn funcs(&self) -> Vec<(&String, &Func)> {
+        let mut funcs = self.funcs.iter().collect::<Vec<_>>();
+        funcs.sort_by(|a, b| a.0.cmp(b.0));
+        funcs
+    }
+    /// Forget every variable, function and result, keeping the settings.
+    pub fn clear(&mut self) {
+        self.vars.clear();
+        self.funcs.clear();
+        self.results.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(env: &Env, name: &str) -> Option<String> {
+        env.get(name).map(Value::to_string)
+    }
+
+    #[test]
+    fn numbers_results_from_one() {
+        let mut env = Env::new();
+        assert_eq!(get(&env, "ans"), None);
+        env.push_result(Value::Num(10));
+        env.push_result(Value::Num(20));
+        assert_eq!(env.result_count(), 2);
+        assert_eq!(get(&env, "ans").as_deref(), Some("20"));
+        assert_eq!(get(&env, "_1").as_deref(), Some("10"));
+        assert_eq!(get(&env, "_2").as_deref(), Some("20"));
+        for name in ["_0", "_3", "_", "_x", "_-1"] {
+            assert_eq!(get(&env, name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn variables_shadow_results_and_constants() {
+        let mut env = Env::new();
+        env.push_result(Value::Num(1));
+        env.bind("ans", Value::Num(2));
+        env.bind("pi", Value::Num(3));
+        assert_eq!(get(&env, "ans").as_deref(), Some("2"));
+        assert_eq!(get(&env, "pi").as_deref(), Some("3"));
+        assert_eq!(get(&env, "e").as_deref(), Some("2.718281828459045"));
+        assert!(env.var("_1").is_none());
+    }
+
+    #[test]
+    fn clear_forgets_results() {
+        let mut env = Env::new();
+        env.push_result(Value::Num(1));
+        env.clear();
+        assert_eq!(env.result_count(), 0);
+        assert_eq!(get(&env, "ans"), None);
+    }
+
+    #[test]
+    fn steps_stop_past_the_limit() {
+        let env = Env::new();
+        for _ in 0..MAX_ITERATIONS {
+            assert!(env.step().is_ok());
+        }
+        assert!(env.step().is_err());
+        env.reset_steps();
+        assert!(env.step().is_ok());
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::builtin;
+use crate::complex::Complex;
+use crate::matrix::Shape;
+use crate::env::{Env, Func};
+use crate::module;
+use crate::node::{Node, Stmt, Value, BinaryOp, Closure};
+use crate::settings::Mode;
+
+/// Nesting limit for calls to user functions.
+const MAX_DEPTH: usize = 256;
+
+/// Limit on the items loops and folds take, over a whole evaluation.
+pub const MAX_ITERATIONS: usize = 10_000_000;
+
+pub enum EvalError {
+    Unbound(String),
+    UnknownFunction(String),
+    Arity {
+        name: String,
+        expect: usize,
+        found: usize
+    },
+    Unsupported(String),
+    DivByZero,
+    Overflow,
+    TooDeep,
+    TooLong,
+    Index {
+        index: i64,
+        len: usize
+    },
+    /// Lists of different lengths, item by item.
+    Lengths(usize, usize),
+    /// Matrices whose shapes do not fit the operation, such as `2x3 * 2x3`.
+    Shape(Shape, Shape),
+    NotSquare(Shape),
+    /// A matrix with more than `MAX_ITERATIONS` items.
+    TooLarge(Shape),
+    Singular,
+    /// A module that cannot be found, read, parsed or run.
+    Module(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Unbound(name) => write!(f, "unbound variable {}", name),
+            EvalError::UnknownFunction(name) => write!(f, "unknown function {}", name),
+            EvalError::Arity { name, expect, found } => {
+                write!(f, "{} expect {} arguments, found {}", name, expect, found)
+            }
+            EvalError::Unsupported(what) => write!(f, "not supported: {}", what),
+            EvalError::DivByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "integer overflow"),
+            EvalError::TooDeep => write!(f, "calls nested deeper than {}", MAX_DEPTH),
+            EvalError::TooLong => write!(f, "more than {} iterations", MAX_ITERATIONS),
+            EvalError::Index { index, len } => write!(f, "index {} out of range for length {}", index, len),
+            EvalError::Lengths(left, right) => write!(f, "lengths {} and {} differ", left, right),
+            EvalError::Shape(left, right) => write!(f, "shapes {} and {} do not match", left, right),
+            EvalError::NotSquare(shape) => write!(f, "{} matrix is not square", shape),
+            EvalError::TooLarge(shape) => write!(f, "{} matrix is too large", shape),
+            EvalError::Singular => write!(f, "matrix is singular"),
+            EvalError::Module(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Local bindings, such as the parameters of a user function, layered over
+/// the environment's variables.
+pub struct Scope<'a> {
+    vars: Vec<(String, Value)>,
+    parent: Option<&'a Scope<'a>>,
+    depth: usize
+}
+
+impl Scope<'_> {
+    fn get(&self, name: &str) -> Option<&Value> {
+        match self.vars.iter().rev().find(|(var, _)| var == name) {
+            Some((_, val)) => Some(val),
+            None => self.parent?.get(name),
+        }
+    }
+    /// Every local binding in reach, outermost first, for a closure to keep.
+    fn captured(&self) -> Vec<(String, Value)> {
+        let mut vars = self.parent.map(Scope::captured).unwrap_or_default();
+        vars.extend(self.vars.iter().cloned());
+        vars
+    }
+    /// A function value bound to `name` here, which calls reach before the
+    /// functions of the same name.
+    fn func(&self, name: &str) -> Option<&Value> {
+        self.get(name).filter(|val| matches!(val, Value::Func(..)))
+    }
+}
+
+/// Apply `op` the way `mode` asks for.
+pub fn binary(mode: Mode, op: &BinaryOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    if op.broadcasts(&left, &right) {
+        return op.broadcast(left, right, &|left, right| binary(mode, op, left, right))
+    }
+    let arithmetic = matches!(op,
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Pow
+    );
+    // a fractional power of a negative number, such as `(-4) ** 0.5`
+    let root = matches!(op, BinaryOp::Pow)
+        && matches!(right, Value::Float(exp) if exp.fract() != 0.0)
+        && Complex::from_value(&left).is_some_and(|z| z.re < 0.0 && z.im == 0.0);
+    match (mode, arithmetic, left, right) {
+        (Mode::Complex, true, left, right) if root => {
+            let left = Complex::from_value(&left).unwrap();
+            op.apply(Value::Complex(left), right)
+        }
+        (Mode::Float | Mode::Complex, true, Value::Num(left), right) => op.apply(Value::Float(left as f64), right),
+        (Mode::Float | Mode::Complex, true, left, Value::Num(right)) => op.apply(left, Value::Float(right as f64)),
+        (_, _, left, right) => op.apply(left, right),
+    }
+}
+
+/// Call a function value, such as a lambda, with evaluated arguments.
+pub fn apply(env: &Env, func: &Value, args: Vec<Value>, depth: usize) -> Result<Value, EvalError> {
+    let closure = match func {
+        Value::Func(closure) => closure,
+        val => return Err(EvalError::Unsupported(format!("calling {}", val))),
+    };
+    if args.len() != closure.params.len() {
+        return Err(EvalError::Arity {
+            name: "lambda".to_string(),
+            expect: closure.params.len(),
+            found: args.len()
+        })
+    }
+    if depth >= MAX_DEPTH {
+        return Err(EvalError::TooDeep)
+    }
+    let mut vars = closure.captured.clone();
+    vars.extend(closure.params.iter().cloned().zip(args));
+    let scope = Scope {
+        vars,
+        parent: None,
+        depth: depth + 1
+    };
+    closure.body.eval_in(env, &scope)
+}
+
+/// Call a user function, a variable holding a function, or a builtin by
+/// name with evaluated arguments.
+pub fn call(env: &Env, name: &str, args: Vec<Value>, depth: usize) -> Result<Value, EvalError> {
+    if let Some(Func { params, body }) = env.func(name) {
+        if args.len() != params.len() {
+            return Err(EvalError::Arity {
+                name: name.to_string(),
+                expect: params.len(),
+                found: args.len()
+            })
+        }
+        if depth >= MAX_DEPTH {
+            return Err(EvalError::TooDeep)
+        }
+        let scope = Scope {
+            vars: params.iter().cloned().zip(args).collect(),
+            parent: None,
+            depth: depth + 1
+        };
+        return body.eval_in(env, &scope)
+    }
+    if let Some(func @ Value::Func(..)) = env.get(name) {
+        return apply(env, func, args, depth)
+    }
+    match builtin::resolve(name, env.settings.mode) {
+        Some(func) => func.call(&args, Some(&|func, args| apply(env, func, args, depth))),
+        None => Err(EvalError::UnknownFunction(name.to_string())),
+    }
+}
+
+/// A user function or builtin named as a value, as in `map(xs, sqrt)`.
+fn named(env: &Env, name: &str) -> Option<Value> {
+    let params: Vec<String> = match (env.func(name), builtin::lookup(name)) {
+        (Some(func), _) => func.params.clone(),
+        (None, Some((_, func))) => func.params.iter().map(|param| param.to_string()).collect(),
+        (None, None) => return None,
+    };
+    let args = params.iter().cloned().map(Node::Var).collect();
+    Some(Value::Func(Arc::new(Closure {
+        params,
+        body: Node::Call(name.to_string(), args),
+        captured: Vec::new()
+    })))
+}
+
+/// `sum(xs)`, `sum(x ** 2 for x in xs)` and the other folds, item by item.
+fn fold(env: &Env, scope: &Scope, name: &str, args: &[Node]) -> Result<Value, EvalError> {
+    let fold = builtin::fold(name).unwrap();
+    let (var, seq, body) = match args {
+        [Node::For(var, seq, body)] => (Some(var), seq.as_ref(), Some(body)),
+        [seq] => (None, seq, None),
+        _ => return Err(EvalError::Arity {
+            name: name.to_string(),
+            expect: 1,
+            found: args.len()
+        }),
+    };
+    let seq = seq.eval_in(env, scope)?;
+    let items = match seq.items() {
+        Some(items) => items,
+        None => return Err(EvalError::Unsupported(format!("{} on {}", name, seq))),
+    };
+    fold.run(items.map(|item| {
+        env.step()?;
+        match (var, body) {
+            (Some(var), Some(body)) => body.eval_for(env, scope, var, item),
+            _ => Ok(item),
+        }
+    }))
+}
+
+impl Node {
+    /// The body of a loop, with `var` bound to `item`.
+    fn eval_for(&self, env: &Env, scope: &Scope, var: &str, item: Value) -> Result<Value, EvalError> {
+        let scope = Scope {
+            vars: vec![(var.to_string(), item)],
+            parent: Some(scope),
+            depth: scope.depth
+        };
+        self.eval_in(env, &scope)
+    }
+    /// Evaluate the tree against `env` without consuming it, so a stored
+    /// formula can be printed, analysed and evaluated again.
+    pub fn eval(&self, env: &Env) -> Result<Value, EvalError> {
+        env.reset_steps();
+        let scope = Scope {
+            vars: Vec::new(),
+            parent: None,
+            depth: 0
+        };
+        self.eval_in(env, &scope)
+    }
+    pub fn eval_in(&self, env: &Env, scope: &Scope) -> Result<Value, EvalError> {
+        match self {
+            Node::Val(val) => Ok(val.clone()),
+            Node::Var(name) => match scope.get(name).or_else(|| env.get(name)) {
+                Some(val) => Ok(val.clone()),
+                None => named(env, name).ok_or_else(|| EvalError::Unbound(name.clone())),
+            },
+            Node::Unary(op, node) => op.apply(node.eval_in(env, scope)?),
+            Node::Binary(op @ (BinaryOp::LogicAnd | BinaryOp::LogicOr), left, right) => {
+                // the right side only runs when the left does not decide
+                let left = left.eval_in(env, scope)?.truthy()?;
+                let res = match (op, left) {
+                    (BinaryOp::LogicAnd, false) => false,
+                    (BinaryOp::LogicOr, true) => true,
+                    _ => right.eval_in(env, scope)?.truthy()?,
+                };
+                Ok(Value::Num(res as i64))
+            }
+            Node::Binary(op, left, right) => {
+                let left = left.eval_in(env, scope)?;
+                let right = right.eval_in(env, scope)?;
+                binary(env.settings.mode, op, left, right)
+            }
+            Node::Call(name, args) if scope.func(name).is_none()
+                && env.func(name).is_none()
+                && !matches!(env.get(name), Some(Value::Func(..)))
+                && builtin::fold(name).is_some() => fold(env, scope, name, args),
+            Node::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_in(env, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match scope.func(name) {
+                    Some(func) => apply(env, func, args, scope.depth),
+                    None => call(env, name, args, scope.depth),
+                }
+            }
+            Node::If(cond, then, other) => match cond.eval_in(env, scope)?.truthy()? {
+                true => then.eval_in(env, scope),
+                false => other.eval_in(env, scope),
+            },
+            Node::For(var, seq, body) => {
+                let seq = seq.eval_in(env, scope)?;
+                let items = match seq.items() {
+                    Some(items) => items,
+                    None => return Err(EvalError::Unsupported(format!("for over {}", seq))),
+                };
+                items
+                    .map(|item| {
+                        env.step()?;
+                        body.eval_for(env, scope, var, item)
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(Value::List)
+            }
+            // `[x * 2 for x in xs]` is the list the loop makes
+            Node::List(items) if let [node @ Node::For(..)] = items.as_slice() => match node.eval_in(env, scope)? {
+                Value::List(items) => Ok(Value::list(items)),
+                val => Ok(val),
+            },
+            Node::List(items) => items
+                .iter()
+                .map(|item| item.eval_in(env, scope))
+                .collect::<Result<_, _>>()
+                .map(Value::list),
+            Node::Index(node, index) => node
+                .eval_in(env, scope)?
+                .index(&index.eval_in(env, scope)?),
+            Node::Lambda(params, body) => Ok(Value::Func(Arc::new(Closure {
+                params: params.clone(),
+                body: body.as_ref().clone(),
+                captured: scope.captured()
+            }))),
+        }
+    }
+}
+
+impl Stmt {
+    /// Run a statement, binding what it assigns or defines. Returns the
+    /// value of expressions and assignments.
+    pub fn exec(&self, env: &mut Env) -> Result<Option<Value>, EvalError> {
+        match self {
+            Stmt::Expr(node) => node.eval(env).map(Some),
+            Stmt::Let(name, node) => {
+                let val = node.eval(env)?;
+                env.bind(name, val.clone());
+                Ok(Some(val))
+            }
+            Stmt::Def(name, params, body) => {
+                let func = Func {
+                    params: params.clone(),
+                    body: body.clone()
+                };
+                env.define(name, func);
+                Ok(None)
+            }
+            Stmt::Import(path) => module::import(env, path).map(|()| None),
+            Stmt::Use(path, name) => module::use_module(env, path, name.as_deref()).map(|()| None),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::env::Env;
+    use crate::parser::tests::{parse, stmt};
+
+    /// Run the statement `src`, giving its value, `error: ...`, or nothing
+    /// for a definition.
+    pub fn run(env: &mut Env, src: &str) -> String {
+        match stmt(src).exec(env) {
+            Ok(Some(val)) => val.to_string(),
+            Ok(None) => String::new(),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn leaves_the_tree_to_evaluate_again() {
+        let mut env = Env::new();
+        let node = parse("x * 2");
+        run(&mut env, "x = 3");
+        assert_eq!(node.eval(&env).ok().unwrap().to_string(), "6");
+        run(&mut env, "x = 4");
+        assert_eq!(node.eval(&env).ok().unwrap().to_string(), "8");
+    }
+
+    #[test]
+    fn reports_typed_errors() {
+        let mut env = Env::new();
+        assert_eq!(run(&mut env, "y + 1"), "error: unbound variable y");
+        assert_eq!(run(&mut env, "nope(1)"), "error: unknown function nope");
+        assert_eq!(run(&mut env, "max(1)"), "error: max expect 2 arguments, found 1");
+        assert_eq!(run(&mut env, "1 / 0"), "error: division by zero");
+        assert_eq!(run(&mut env, "1 % 0"), "error: division by zero");
+        assert_eq!(run(&mut env, "9223372036854775807 + 1"), "error: integer overflow");
+        assert_eq!(run(&mut env, "\"a\" - 1"), "error: not supported: Sub on \"a\" and 1");
+    }
+
+    #[test]
+    fn factorial() {
+        let mut env = Env::new();
+        assert_eq!(run(&mut env, "0!"), "1");
+        assert_eq!(run(&mut env, "5!"), "120");
+        assert_eq!(run(&mut env, "20!"), "2432902008176640000");
+        assert_eq!(run(&mut env, "21!"), "error: integer overflow");
+        assert_eq!(run(&mut env, "(-3)!"), "error: not supported: factorial of -3");
+    }
+
+    #[test]
+    fn recursion_stops_at_the_depth_limit() {
+        let mut env = Env::new();
+        run(&mut env, "f(n) = f(n + 1)");
+        assert_eq!(run(&mut env, "f(0)"), "error: calls nested deeper than 256");
+        run(&mut env, "fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)");
+        assert_eq!(run(&mut env, "fib(15)"), "610");
+    }
+
+    #[test]
+    fn compares_and_branches() {
+        let mut env = Env::new();
+        for (src, res) in [("1 < 2", "1"), ("2 <= 1", "0"), ("1 == 1.0", "1"), ("1 != 2", "1"), ("\"a\" < \"b\"", "1"),
+                           ("0.0 / 0.0 == 0.0 / 0.0", "0"), ("1 && 2", "1"), ("0 || 0.0", "0"), ("!(1 < 2)", "0"),
+                           ("if 1 < 2 then \"y\" else \"n\"", "\"y\""), ("0 ? 1 : 2 ? 3 : 4", "3")] {
+            assert_eq!(run(&mut env, src), res, "{}", src);
+        }
+        assert_eq!(run(&mut env, "1 < \"a\""), "error: not supported: Lt on 1 and \"a\"");
+        assert_eq!(run(&mut env, "\"s\" ? 1 : 2"), "error: not supported: condition \"s\"");
+    }
+
+    #[test]
+    fn evaluates_only_what_is_taken() {
+        let mut env = Env::new();
+        for src in ["0 && 1 / 0", "1 || 1 / 0", "if 0 then 1 / 0 else 1", "1 ? 1 : nope(2)"] {
+            assert!(!run(&mut env, src).starts_with("error"), "{}", src);
+        }
+        assert_eq!(run(&mut env, "1 && 1 / 0"), "error: division by zero");
+    }
+}
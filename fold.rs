@@ -0,0 +1,132 @@
+use crate::node::{Node, Value, UnaryOp, BinaryOp};
+
+/// Rewriting pass that takes a tree apart and builds a new one. The
+/// defaults fold every child and rebuild the same variant, so a pass only
+/// overrides the variants it rewrites.
+pub trait Folder {
+    fn fold_node(&mut self, node: Node) -> Node {
+        walk_node(self, node)
+    }
+    fn fold_val(&mut self, val: Value) -> Node {
+        Node::Val(val)
+    }
+    fn fold_var(&mut self, name: String) -> Node {
+        Node::Var(name)
+    }
+    fn fold_unary(&mut self, op: UnaryOp, node: Node) -> Node {
+        walk_unary(self, op, node)
+    }
+    fn fold_binary(&mut self, op: BinaryOp, left: Node, right: Node) -> Node {
+        walk_binary(self, op, left, right)
+    }
+    fn fold_call(&mut self, name: String, args: Vec<Node>) -> Node {
+        walk_call(self, name, args)
+    }
+    fn fold_if(&mut self, cond: Node, then: Node, other: Node) -> Node {
+        walk_if(self, cond, then, other)
+    }
+    fn fold_for(&mut self, var: String, seq: Node, body: Node) -> Node {
+        walk_for(self, var, seq, body)
+    }
+    fn fold_list(&mut self, items: Vec<Node>) -> Node {
+        walk_list(self, items)
+    }
+    fn fold_index(&mut self, node: Node, index: Node) -> Node {
+        walk_index(self, node, index)
+    }
+    fn fold_lambda(&mut self, params: Vec<String>, body: Node) -> Node {
+        walk_lambda(self, params, body)
+    }
+}
+
+pub fn walk_node<F: Folder + ?Sized>(folder: &mut F, node: Node) -> Node {
+    match node {
+        Node::Val(val) => folder.fold_val(val),
+        Node::Var(name) => folder.fold_var(name),
+        Node::Unary(op, node) => folder.fold_unary(op, *node),
+        Node::Binary(op, left, right) => folder.fold_binary(op, *left, *right),
+        Node::Call(name, args) => folder.fold_call(name, args),
+        Node::If(cond, then, other) => folder.fold_if(*cond, *then, *other),
+        Node::For(var, seq, body) => folder.fold_for(var, *seq, *body),
+        Node::List(items) => folder.fold_list(items),
+        Node::Index(node, index) => folder.fold_index(*node, *index),
+        Node::Lambda(params, body) => folder.fold_lambda(params, *body),
+    }
+}
+
+pub fn walk_unary<F: Folder + ?Sized>(folder: &mut F, op: UnaryOp, node: Node) -> Node {
+    Node::Unary(op, Box::new(folder.fold_node(node)))
+}
+
+pub fn walk_binary<F: Folder + ?Sized>(folder: &mut F, op: BinaryOp, left: Node, right: Node) -> Node {
+    let left = folder.fold_node(left);
+    let right = folder.fold_node(right);
+    Node::Binary(op, Box::new(left), Box::new(right))
+}
+
+pub fn walk_call<F: Folder + ?Sized>(folder: &mut F, name: String, args: Vec<Node>) -> Node {
+    let args = args
+        .into_iter()
+        .map(|arg| folder.fold_node(arg))
+        .collect();
+    Node::Call(name, args)
+}
+
+pub fn walk_if<F: Folder + ?Sized>(folder: &mut F, cond: Node, then: Node, other: Node) -> Node {
+    let cond = folder.fold_node(cond);
+    let then = folder.fold_node(then);
+    let other = folder.fold_node(other);
+    Node::If(Box::new(cond), Box::new(then), Box::new(other))
+}
+
+pub fn walk_for<F: Folder + ?Sized>(folder: &mut F, var: String, seq: Node, body: Node) -> Node {
+    let seq = folder.fold_node(seq);
+    let body = folder.fold_node(body);
+    Node::For(var, Box::new(seq), Box::new(body))
+}
+
+pub fn walk_list<F: Folder + ?Sized>(folder: &mut F, items: Vec<Node>) -> Node {
+    let items = items
+        .into_iter()
+        .map(|item| folder.fold_node(item))
+        .collect();
+    Node::List(items)
+}
+
+pub fn walk_index<F: Folder + ?Sized>(folder: &mut F, node: Node, index: Node) -> Node {
+    let node = folder.fold_node(node);
+    let index = folder.fold_node(index);
+    Node::Index(Box::new(node), Box::new(index))
+}
+
+pub fn walk_lambda<F: Folder + ?Sized>(folder: &mut F, params: Vec<String>, body: Node) -> Node {
+    Node::Lambda(params, Box::new(folder.fold_node(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+
+    struct Rename;
+
+    impl Folder for Rename {
+        fn fold_var(&mut self, name: String) -> Node {
+            Node::Var(name.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn default_walk_keeps_the_shape() {
+        struct Same;
+        impl Folder for Same {}
+        let src = "if a > 1 then f(-b, [c, d[1]]) else |x| x ** 2 for x in 1..n";
+        assert_eq!(Same.fold_node(parse(src)).to_string(), parse(src).to_string());
+    }
+
+    #[test]
+    fn overrides_rebuild_the_tree() {
+        let node = Rename.fold_node(parse("a + f(b, 1)"));
+        assert_eq!(node.to_string(), "(Add A (Call f B 1))");
+    }
+}
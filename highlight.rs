@@ -0,0 +1,172 @@
+use crate::env::Env;
+use crate::lexer::Lexer;
+use crate::node::{Node, Stmt};
+use crate::parser::{Parser, Result};
+use crate::token::{Token, Span};
+use crate::visit::Visitor;
+
+const NUMBER: &str = "33";
+const NAME: &str = "36";
+const OPERATOR: &str = "35";
+const PAREN: &str = "1";
+const MATCHED: &str = "1;4";
+const UNKNOWN: &str = "31";
+
+fn color(tok: &Token) -> Option<&'static str> {
+    match tok {
+        Token::Num(..) | Token::Float(..) | Token::Imag(..) | Token::Str(..) => Some(NUMBER),
+        Token::Name(..) => Some(NAME),
+        Token::OpenParen | Token::CloseParen | Token::OpenBracket | Token::CloseBracket => Some(PAREN),
+        Token::Unknown(..) | Token::Malformed(..) => Some(UNKNOWN),
+        Token::Comma | Token::Line | Token::End => None,
+        _ => Some(OPERATOR),
+    }
+}
+
+fn is_open(tok: &Token) -> bool {
+    matches!(tok, Token::OpenParen | Token::OpenBracket)
+}
+
+fn is_close(tok: &Token) -> bool {
+    matches!(tok, Token::CloseParen | Token::CloseBracket)
+}
+
+/// Index of the paren or bracket matching the one at `index`.
+fn partner(toks: &[(Token, Span)], index: usize) -> Option<usize> {
+    let mut depth = 0;
+    match &toks[index].0 {
+        tok if is_open(tok) => {
+            for (at, (tok, _)) in toks.iter().enumerate().skip(index) {
+                match tok {
+                    tok if is_open(tok) => depth += 1,
+                    tok if is_close(tok) => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Some(at)
+                }
+            }
+            None
+        }
+        tok if is_close(tok) => {
+            for (at, (tok, _)) in toks.iter().enumerate().take(index + 1).rev() {
+                match tok {
+                    tok if is_close(tok) => depth += 1,
+                    tok if is_open(tok) => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Some(at)
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// `line` with ANSI colours per token. The paren under or just before char
+/// offset `pos` is underlined together with its match.
+pub fn highlight(line: &str, pos: usize) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let toks = Lexer::new(line.chars())
+        .lenient()
+        .spanned()
+        .collect::<Vec<_>>();
+    let is_paren = |tok: &Token| is_open(tok) || is_close(tok);
+    let cursor = toks
+        .iter()
+        .position(|(tok, span)| is_paren(tok) && span.start == pos)
+        .or_else(|| toks.iter().position(|(tok, span)| is_paren(tok) && span.end == pos));
+    let matched = cursor
+        .and_then(|index| Some((index, partner(&toks, index)?)));
+
+    let mut out = String::new();
+    let mut at = 0;
+    for (index, (tok, span)) in toks.iter().enumerate() {
+        out.extend(&chars[at..span.start]);
+        let text = chars[span.start..span.end].iter().collect::<String>();
+        let color = match matched {
+            Some((a, b)) if index == a || index == b => Some(MATCHED),
+            _ => color(tok),
+        };
+        match color {
+            Some(color) => out.push_str(&format!("\x1b[{}m{}\x1b[0m", color, text)),
+            None => out.push_str(&text),
+        }
+        at = span.end;
+    }
+    out.extend(&chars[at..]);
+    out
+}
+
+/// Finds calls and loops, which may run for longer than a keystroke can
+/// wait.
+struct Costly(bool);
+
+impl Visitor for Costly {
+    fn visit_call(&mut self, _name: &str, _args: &[Node]) {
+        self.0 = true
+    }
+    fn visit_for(&mut self, _var: &str, _seq: &Node, _body: &Node) {
+        self.0 = true
+    }
+}
+
+/// Preview of what `src` evaluates to, or of its parse error. Nothing for
+/// input that is still incomplete, that fails to evaluate, or that may be
+/// slow to.
+pub fn hint(env: &Env, src: &str) -> Option<String> {
+    let node = match Parser::new(Lexer::new(src.chars()).lenient()).parse_stmt() {
+        Result::Ok(Stmt::Expr(Node::Val(..))) => return None,
+        Result::Ok(Stmt::Expr(node)) | Result::Ok(Stmt::Let(_, node)) => node,
+        Result::Ok(Stmt::Def(..) | Stmt::Import(..) | Stmt::Use(..)) | Result::None | Result::Incomplete(..) => return None,
+        Result::Err(err) => return Some(format!("error: {}", err)),
+    };
+    let mut costly = Costly(false);
+    costly.visit_node(&node);
+    if costly.0 {
+        return None
+    }
+    node.eval(env)
+        .ok()
+        .map(|val| format!("= {}", env.settings.show(&val)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::tests::run;
+
+    #[test]
+    fn colours_tokens_and_matching_parens() {
+        assert_eq!(highlight("x + 1", 0), "\x1b[36mx\x1b[0m \x1b[35m+\x1b[0m \x1b[33m1\x1b[0m");
+        assert_eq!(highlight("(1)", 3), "\x1b[1;4m(\x1b[0m\x1b[33m1\x1b[0m\x1b[1;4m)\x1b[0m");
+        assert_eq!(highlight("$", 0), "\x1b[31m$\x1b[0m");
+        assert_eq!(highlight("99999999999999999999", 0), "\x1b[31m99999999999999999999\x1b[0m");
+    }
+
+    #[test]
+    fn hints_values_and_errors() {
+        let mut env = Env::new();
+        run(&mut env, "x = 4");
+        assert_eq!(hint(&env, "x * 2").as_deref(), Some("= 8"));
+        assert_eq!(hint(&env, "y = x + 1").as_deref(), Some("= 5"));
+        assert_eq!(hint(&env, "1 $").as_deref(), Some("error: unknow character $"));
+        assert_eq!(hint(&env, "99999999999999999999").as_deref(), Some("error: integer too large 99999999999999999999"));
+        assert_eq!(hint(&env, "1 +"), None);
+        assert_eq!(hint(&env, "1 / 0"), None);
+        assert_eq!(hint(&env, "42"), None);
+    }
+
+    #[test]
+    fn skips_what_may_be_slow() {
+        let mut env = Env::new();
+        run(&mut env, "f(n) = f(n + 1)");
+        assert_eq!(hint(&env, "f(0)"), None);
+        assert_eq!(hint(&env, "max(1, 2) + 1"), None);
+        assert_eq!(hint(&env, "sum(i for i in 1..10000000)"), None);
+        assert_eq!(hint(&env, "[i for i in 1..3]"), None);
+        assert_eq!(hint(&env, "map([1], |x| x)"), None);
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::parser::INDEX;
+use crate::token::Token;
+use crate::node::{Node, Value, PrefixOp, PostfixOp, BinaryOp};
+
+/// Infix form of a tree, with only the parentheses the parser needs to
+/// build the same tree back.
+pub struct Infix<'a>(&'a Node);
+
+impl Node {
+    pub fn infix(&self) -> Infix<'_> {
+        Infix(self)
+    }
+}
+
+impl fmt::Display for Infix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_node(f, self.0, 0, None)
+    }
+}
+
+// `power` is the binding power the parser reads this node at, `next` is the
+// left binding power of the operator printed right after it, if any.
+pub fn needs_parens(node: &Node, power: u8, next: Option<u8>) -> bool {
+    match node {
+        Node::Unary(op, ..) if let Some(op) = Option::<PostfixOp>::from(op) => {
+            op.binding_power() < power
+        }
+        Node::Binary(op, ..) => {
+            let (left_power, right_power) = op.binding_power();
+            left_power < power || next.is_some_and(|next| next >= right_power)
+        }
+        // the else branch, or the lambda body, would take in whatever follows
+        Node::If(..) | Node::For(..) | Node::Lambda(..) => power > 0 || next.is_some(),
+        Node::Val(Value::Func(..)) => power > 0 || next.is_some(),
+        Node::Val(Value::Range(..)) => power > 0 || next.is_some(),
+        Node::Unary(op, ..) if let Some(op) = Option::<PrefixOp>::from(op) => op.binding_power() < power,
+        // `1+2i` prints as a sum
+        Node::Val(Value::Complex(z)) if z.re != 0.0 => {
+            let (left_power, right_power) = BinaryOp::Add.binding_power();
+            left_power < power || next.is_some_and(|next| next >= right_power)
+        }
+        Node::Val(Value::Complex(z)) => z.im.is_sign_negative() && power >= INDEX,
+        Node::Val(Value::Num(n)) => *n < 0 && power >= INDEX,
+        Node::Val(Value::Float(x)) => x.is_sign_negative() && power >= INDEX,
+        _ => false,
+    }
+}
+
+// `for` ends the parts of an if and the body of another loop, so a loop in
+// one keeps its parentheses.
+fn write_branch(f: &mut fmt::Formatter, node: &Node) -> fmt::Result {
+    match node {
+        Node::For(..) => write_node(f, node, 1, None),
+        node => write_node(f, node, 0, None),
+    }
+}
+
+fn write_node(f: &mut fmt::Formatter, node: &Node, power: u8, next: Option<u8>) -> fmt::Result {
+    let parens = needs_parens(node, power, next);
+    let (power, next) = if parens { (0, None) } else { (power, next) };
+    if parens {
+        write!(f, "(")?
+    }
+    match node {
+        Node::Val(val) => write!(f, "{}", val)?,
+        Node::Var(name) => write!(f, "{}", name)?,
+
+        Node::Unary(op, node) => {
+            if let Some(prefix) = Option::<PrefixOp>::from(op) {
+                write!(f, "{}", Token::from(op))?;
+                write_node(f, node, prefix.binding_power(), next)?
+            }
+            else if let Some(postfix) = Option::<PostfixOp>::from(op) {
+                write_node(f, node, power, Some(postfix.binding_power()))?;
+                write!(f, "{}", Token::from(op))?
+            }
+        }
+        Node::Binary(op, left, right) => {
+            let (left_power, right_power) = op.binding_power();
+            write_node(f, left, power, Some(left_power))?;
+            match op {
+                BinaryOp::Range | BinaryOp::RangeIncl => write!(f, "{}", Token::from(op))?,
+                _ => write!(f, " {} ", Token::from(op))?,
+            }
+            write_node(f, right, right_power, next)?
+        }
+        Node::Call(name, args) => {
+            write!(f, "{}(", name)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?
+                }
+                write_node(f, arg, 0, None)?
+            }
+            write!(f, ")")?
+        }
+        Node::If(cond, then, other) => {
+            write!(f, "if ")?;
+            write_branch(f, cond)?;
+            write!(f, " then ")?;
+            write_branch(f, then)?;
+            write!(f, " else ")?;
+            write_branch(f, other)?
+        }
+        Node::For(var, seq, body) => {
+            write_branch(f, body)?;
+            write!(f, " for {} in ", var)?;
+            write_node(f, seq, 1, None)?
+        }
+        Node::List(items) => {
+            write!(f, "[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?
+                }
+                write_node(f, item, 0, None)?
+            }
+            write!(f, "]")?
+        }
+        Node::Index(node, index) => {
+            write_node(f, node, INDEX, None)?;
+            write!(f, "[")?;
+            write_node(f, index, 0, None)?;
+            write!(f, "]")?
+        }
+        Node::Lambda(params, body) => {
+            write!(f, "|{}| ", params.join(", "))?;
+            write_branch(f, body)?
+        }
+    }
+    if parens {
+        write!(f, ")")?
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::node::{Value, UnaryOp, BinaryOp};
+    use crate::parser::tests::parse;
+
+    const UNARY: &[UnaryOp] = &[UnaryOp::Neg, UnaryOp::BitNot, UnaryOp::LogicNot, UnaryOp::Fac];
+
+    const BINARY: &[BinaryOp] = &[
+        BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div, BinaryOp::Mod, BinaryOp::Pow,
+        BinaryOp::BitAnd, BinaryOp::BitOr, BinaryOp::BitXor, BinaryOp::ShiftLeft, BinaryOp::ShiftRight,
+        BinaryOp::Range, BinaryOp::RangeIncl, BinaryOp::Concat,
+    ];
+
+    /// Random trees, from xorshift, which is enough to vary them without a
+    /// dependency.
+    pub struct Rng(pub u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+        fn name(&mut self) -> String {
+            ["a", "b", "x", "y"][self.below(4)].to_string()
+        }
+        fn nodes(&mut self, depth: usize, len: usize) -> Vec<Node> {
+            (0..self.below(len + 1)).map(|_| self.node(depth)).collect()
+        }
+        fn boxed(&mut self, depth: usize) -> Box<Node> {
+            Box::new(self.node(depth))
+        }
+        pub fn node(&mut self, depth: usize) -> Node {
+            let leaf = |rng: &mut Self| match rng.below(2) {
+                0 => Node::Val(Value::Num(rng.below(100) as i64)),
+                _ => Node::Var(rng.name()),
+            };
+            if depth == 0 {
+                return leaf(self)
+            }
+            let depth = depth - 1;
+            match self.below(12) {
+                0 => leaf(self),
+                1 | 2 => Node::Unary(UNARY[self.below(UNARY.len())].clone(), self.boxed(depth)),
+                3..=5 => Node::Binary(BINARY[self.below(BINARY.len())].clone(), self.boxed(depth), self.boxed(depth)),
+                6 => Node::Call(self.name(), self.nodes(depth, 3)),
+                7 => Node::If(self.boxed(depth), self.boxed(depth), self.boxed(depth)),
+                8 => Node::For(self.name(), self.boxed(depth), self.boxed(depth)),
+                9 => Node::List(self.nodes(depth, 3)),
+                10 => Node::Index(self.boxed(depth), self.boxed(depth)),
+                _ => Node::Lambda(vec![self.name()], self.boxed(depth)),
+            }
+        }
+    }
+
+    #[test]
+    fn prints_minimal_parentheses() {
+        assert_eq!(parse("(1 + 2) * 3 - (4 - 5)").infix().to_string(), "(1 + 2) * 3 - (4 - 5)");
+        assert_eq!(parse("((a ** b) ** c)").infix().to_string(), "a ** b ** c");
+        assert_eq!(parse("a ** (b ** c)").infix().to_string(), "a ** (b ** c)");
+        assert_eq!(parse("(-a)!").infix().to_string(), "-a!");
+        assert_eq!(parse("-(a!)").infix().to_string(), "-(a!)");
+        assert_eq!(parse("f((1 + 2), -(x))").infix().to_string(), "f(1 + 2, -x)");
+        assert_eq!(parse("(if a then b else c) + 1").infix().to_string(), "(if a then b else c) + 1");
+        assert_eq!(parse("f((x for x in xs))").infix().to_string(), "f(x for x in xs)");
+        assert_eq!(parse("(x for x in xs) for y in ys").infix().to_string(), "(x for x in xs) for y in ys");
+    }
+
+    #[test]
+    fn parses_back_to_the_same_tree() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..5000 {
+            let node = rng.node(4);
+            let src = node.infix().to_string();
+            assert_eq!(parse(&src).to_string(), node.to_string(), "{}", src);
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt::Write;
+
+use crate::token::{Token, Span};
+use crate::node::{Node, Stmt, Value, UnaryOp, BinaryOp};
+
+/// JSON form of the calculator's types, laid out the way serde's default
+/// externally tagged enums are, so consumers can derive `Deserialize` on
+/// mirror types.
+///
+/// ```text
+/// Span     {"start": 0, "end": 2}                  char offsets, end exclusive
+/// Token    "Plus" | {"Num": 1} | {"Float": 1.5} | {"Imag": 2.0} | {"Str": "s"}
+///        | {"Name": "x"}
+///        | {"Unknown": "$"}                        unreadable character
+///        | {"Malformed": "99999999999999999999"}  unreadable literal
+/// token    {"token": Token, "span": Span}          as emitted in a line
+/// UnaryOp  "Neg" | "BitNot" | "LogicNot" | "Fac"
+/// BinaryOp "Add" | "Sub" | "Mul" | ...             same names as Display
+/// Value    {"Num": 1} | {"Float": 1.5} | {"Str": "s"}   non-finite floats are null
+///        | {"Complex": {"re": 1.0, "im": 2.0}}
+///        | {"Range": {"start": 1, "end": 5, "step": 1, "inclusive": false}}
+///        | {"List": [Value, ...]}
+///        | {"Matrix": [[Value, ...], ...]}         row by row
+///        | {"Func": [["x", ...], Node]}             captured variables left out
+/// Node     {"Val": Value} | {"Var": "x"}
+///        | {"Unary": [UnaryOp, Node]}
+///        | {"Binary": [BinaryOp, Node, Node]}
+///        | {"Call": ["f", [Node, ...]]}
+///        | {"If": [Node, Node, Node]}
+///        | {"For": ["x", Node, Node]}             variable, sequence, body
+///        | {"List": [Node, ...]} | {"Index": [Node, Node]}
+///        | {"Lambda": [["x", ...], Node]}
+/// Stmt     {"Expr": Node} | {"Let": ["x", Node]} | {"Def": ["f", ["x", ...], Node]}
+///        | {"Import": "file"} | {"Use": [["a", ...], "f" | null]}
+/// line     {"tokens": [token, ...], "ast": Stmt | null,
+///           "value": Value | null, "error": "message" | null}
+/// ```
+///
+/// Only tokens carry spans; AST nodes have none, so map a node back to the
+/// source through the tokens of its line.
+pub trait ToJson {
+    fn write_json(&self, out: &mut String);
+
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+}
+
+pub fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str(r#"\""#),
+            '\\' => out.push_str(r"\\"),
+            '\n' => out.push_str(r"\n"),
+            '\r' => out.push_str(r"\r"),
+            '\t' => out.push_str(r"\t"),
+            c if (c as u32) < 0x20 => write!(out, r"\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_tagged(out: &mut String, tag: &str, val: &dyn ToJson) {
+    out.push('{');
+    write_str(out, tag);
+    out.push(':');
+    val.write_json(out);
+    out.push('}');
+}
+
+impl ToJson for str {
+    fn write_json(&self, out: &mut String) {
+        write_str(out, self)
+    }
+}
+
+impl ToJson for String {
+    fn write_json(&self, out: &mut String) {
+        write_str(out, self)
+    }
+}
+
+impl ToJson for i64 {
+    fn write_json(&self, out: &mut String) {
+        write!(out, "{}", self).unwrap()
+    }
+}
+
+impl ToJson for f64 {
+    fn write_json(&self, out: &mut String) {
+        if self.is_finite() {
+            write!(out, "{:?}", self).unwrap()
+        }
+        else {
+            out.push_str("null")
+        }
+    }
+}
+
+impl <T: ToJson> ToJson for Option<T> {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Some(val) => val.write_json(out),
+            None => out.push_str("null"),
+        }
+    }
+}
+
+impl <T: ToJson> ToJson for [T] {
+    fn write_json(&self, out: &mut String) {
+        out.push('[');
+        for (i, val) in self.iter().enumerate() {
+            if i > 0 {
+                out.push(',')
+            }
+            val.write_json(out)
+        }
+        out.push(']');
+    }
+}
+
+impl <T: ToJson> ToJson for Vec<T> {
+    fn write_json(&self, out: &mut String) {
+        self.as_slice().write_json(out)
+    }
+}
+
+impl <T: ToJson + ?Sized> ToJson for &T {
+    fn write_json(&self, out: &mut String) {
+        (*self).write_json(out)
+    }
+}
+
+impl ToJson for Span {
+    fn write_json(&self, out: &mut String) {
+        write!(out, r#"{{"start":{},"end":{}}}"#, self.start, self.end).unwrap()
+    }
+}
+
+impl ToJson for Token {
+    fn write_json(&self, out: &mut String) {
+        let tag = match self {
+            Token::End => "End",
+
+            Token::Line => "Line",
+
+            Token::Plus => "Plus",
+            Token::PlusPlus => "PlusPlus",
+            Token::Dash => "Dash",
+
+            Token::Star => "Star",
+            Token::Slash => "Slash",
+            Token::Percent => "Percent",
+
+            Token::StarStar => "StarStar",
+
+            Token::And => "And",
+            Token::Pipe => "Pipe",
+            Token::Hat => "Hat",
+
+            Token::Worm => "Worm",
+            Token::Exc => "Exc",
+
+            Token::LessLess => "LessLess",
+            Token::MoreMore => "MoreMore",
+
+            Token::Less => "Less",
+            Token::More => "More",
+            Token::LessEqual => "LessEqual",
+            Token::MoreEqual => "MoreEqual",
+            Token::EqualEqual => "EqualEqual",
+            Token::ExcEqual => "ExcEqual",
+
+            Token::AndAnd => "AndAnd",
+            Token::PipePipe => "PipePipe",
+
+            Token::Question => "Question",
+            Token::Colon => "Colon",
+
+            Token::OpenParen => "OpenParen",
+            Token::CloseParen => "CloseParen",
+            Token::OpenBracket => "OpenBracket",
+            Token::CloseBracket => "CloseBracket",
+
+            Token::Comma => "Comma",
+            Token::Dot => "Dot",
+            Token::DotDot => "DotDot",
+            Token::DotDotEqual => "DotDotEqual",
+            Token::ColonColon => "ColonColon",
+
+            Token::Equal => "Equal",
+
+            Token::Num(n) => return write_tagged(out, "Num", n),
+            Token::Float(x) => return write_tagged(out, "Float", x),
+            Token::Imag(x) => return write_tagged(out, "Imag", x),
+            Token::Str(s) => return write_tagged(out, "Str", s),
+
+            Token::Name(name) => return write_tagged(out, "Name", name),
+
+            Token::Unknown(c) => return write_tagged(out, "Unknown", &c.to_string()),
+            Token::Malformed(text, _) => return write_tagged(out, "Malformed", text),
+        };
+        write_str(out, tag)
+    }
+}
+
+impl ToJson for (Token, Span) {
+    fn write_json(&self, out: &mut String) {
+        out.push_str(r#"{"token":"#);
+        self.0.write_json(out);
+        out.push_str(r#","span":"#);
+        self.1.write_json(out);
+        out.push('}');
+    }
+}
+
+impl ToJson for UnaryOp {
+    fn write_json(&self, out: &mut String) {
+        write_str(out, &self.to_string())
+    }
+}
+
+impl ToJson for BinaryOp {
+    fn write_json(&self, out: &mut String) {
+        write_str(out, &self.to_string())
+    }
+}
+
+impl ToJson for Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Num(n) => write_tagged(out, "Num", n),
+            Value::Float(x) => write_tagged(out, "Float", x),
+            Value::Complex(z) => {
+                out.push_str(r#"{"Complex":{"re":"#);
+                z.re.write_json(out);
+                out.push_str(r#","im":"#);
+                z.im.write_json(out);
+                out.push_str("}}");
+            }
+            Value::Str(s) => write_tagged(out, "Str", s),
+            Value::Range(range) => {
+                write!(out, r#"{{"Range":{{"start":{},"end":{},"step":{},"inclusive":{}}}}}"#,
+                    range.start, range.end, range.step, range.inclusive).unwrap()
+            }
+            Value::List(items) => write_tagged(out, "List", items),
+            Value::Matrix(matrix) => {
+                let rows = matrix.items
+                    .chunks(matrix.cols)
+                    .map(<[Value]>::to_vec)
+                    .collect::<Vec<_>>();
+                write_tagged(out, "Matrix", &rows)
+            }
+            Value::Func(closure) => write_lambda(out, "Func", &closure.params, &closure.body),
+        }
+    }
+}
+
+impl ToJson for Node {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Node::Val(val) => write_tagged(out, "Val", val),
+            Node::Var(name) => write_tagged(out, "Var", name),
+            Node::Unary(op, node) => {
+                out.push_str(r#"{"Unary":["#);
+                op.write_json(out);
+                out.push(',');
+                node.write_json(out);
+                out.push_str("]}");
+            }
+            Node::Binary(op, left, right) => {
+                out.push_str(r#"{"Binary":["#);
+                op.write_json(out);
+                out.push(',');
+                left.write_json(out);
+                out.push(',');
+                right.write_json(out);
+                out.push_str("]}");
+            }
+            Node::Call(name, args) => {
+                out.push_str(r#"{"Call":["#);
+                write_str(out, name);
+                out.push(',');
+                args.write_json(out);
+                out.push_str("]}");
+            }
+            Node::If(cond, then, other) => {
+                out.push_str(r#"{"If":["#);
+                cond.write_json(out);
+                out.push(',');
+                then.write_json(out);
+                out.push(',');
+                other.write_json(out);
+                out.push_str("]}");
+            }
+            Node::For(var, seq, body) => {
+                out.push_str(r#"{"For":["#);
+                write_str(out, var);
+                out.push(',');
+                seq.write_json(out);
+                out.push(',');
+                body.write_json(out);
+                out.push_str("]}");
+            }
+            Node::List(items) => write_tagged(out, "List", items),
+            Node::Index(node, index) => {
+                out.push_str(r#"{"Index":["#);
+                node.write_json(out);
+                out.push(',');
+                index.write_json(out);
+                out.push_str("]}");
+            }
+            Node::Lambda(params, body) => write_lambda(out, "Lambda", params, body),
+        }
+    }
+}
+
+fn write_lambda(out: &mut String, tag: &str, params: &[String], body: &Node) {
+    write!(out, r#"{{"{}":["#, tag).unwrap();
+    params.write_json(out);
+    out.push(',');
+    body.write_json(out);
+    out.push_str("]}");
+}
+
+impl ToJson for Stmt {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Stmt::Expr(node) => write_tagged(out, "Expr", node),
+            Stmt::Let(name, node) => {
+                out.push_str(r#"{"Let":["#);
+                write_str(out, name);
+                out.push(',');
+                node.write_json(out);
+                out.push_str("]}");
+            }
+            Stmt::Def(name, params, node) => {
+                out.push_str(r#"{"Def":["#);
+                write_str(out, name);
+                out.push(',');
+                params.write_json(out);
+                out.push(',');
+                node.write_json(out);
+                out.push_str("]}");
+            }
+            Stmt::Import(path) => write_tagged(out, "Import", path),
+            Stmt::Use(path, name) => {
+                out.push_str(r#"{"Use":["#);
+                path.write_json(out);
+                out.push(',');
+                name.write_json(out);
+                out.push_str("]}");
+            }
+        }
+    }
+}
+
+/// One line of `--json` output.
+pub fn line(tokens: &[(Token, Span)], ast: Option<&Stmt>, value: Option<&Value>, error: Option<&str>) -> String {
+    format!(r#"{{"tokens":{},"ast":{},"value":{},"error":{}}}"#,
+        tokens.to_json(), ast.to_json(), value.to_json(), error.to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::tests::{parse, stmt};
+    use crate::range::Range;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!("a\"b\\c\nd\u{1}".to_json(), r#""a\"b\\c\nd\u0001""#);
+    }
+
+    #[test]
+    fn tags_every_node() {
+        assert_eq!(parse("-x").to_json(), r#"{"Unary":["Neg",{"Var":"x"}]}"#);
+        assert_eq!(parse("1 + x").to_json(), r#"{"Binary":["Add",{"Val":{"Num":1}},{"Var":"x"}]}"#);
+        assert_eq!(parse("f(a, 2)").to_json(), r#"{"Call":["f",[{"Var":"a"},{"Val":{"Num":2}}]]}"#);
+        assert_eq!(parse("x for x in 1..=2").to_json(),
+                   r#"{"For":["x",{"Binary":["RangeIncl",{"Val":{"Num":1}},{"Val":{"Num":2}}]},{"Var":"x"}]}"#);
+        assert_eq!(parse("[x][0]").to_json(), r#"{"Index":[{"List":[{"Var":"x"}]},{"Val":{"Num":0}}]}"#);
+        assert_eq!(parse("|x| x").to_json(), r#"{"Lambda":[["x"],{"Var":"x"}]}"#);
+        assert_eq!(Value::Str("s".to_string()).to_json(), r#"{"Str":"s"}"#);
+        assert_eq!(Value::List(vec![Value::Num(1), Value::Str("s".to_string())]).to_json(), r#"{"List":[{"Num":1},{"Str":"s"}]}"#);
+        let matrix = Value::list(vec![
+            Value::list(vec![Value::Num(1), Value::Num(2)]),
+            Value::list(vec![Value::Num(3), Value::Num(4)]),
+        ]);
+        assert_eq!(matrix.to_json(), r#"{"Matrix":[[{"Num":1},{"Num":2}],[{"Num":3},{"Num":4}]]}"#);
+        assert_eq!(Value::Range(Range::new(1, 5, false)).to_json(), r#"{"Range":{"start":1,"end":5,"step":1,"inclusive":false}}"#);
+    }
+
+    #[test]
+    fn tags_every_statement() {
+        assert_eq!(stmt("-x").to_json(), r#"{"Expr":{"Unary":["Neg",{"Var":"x"}]}}"#);
+        assert_eq!(stmt("x = 1").to_json(), r#"{"Let":["x",{"Val":{"Num":1}}]}"#);
+        assert_eq!(stmt("f(a) = a").to_json(), r#"{"Def":["f",["a"],{"Var":"a"}]}"#);
+        assert_eq!(stmt("use a::b").to_json(), r#"{"Use":[["a"],"b"]}"#);
+        assert_eq!(stmt("import \"m.calc\"").to_json(), r#"{"Import":"m.calc"}"#);
+    }
+
+    #[test]
+    fn writes_a_line() {
+        let tokens = Lexer::new("1 +".chars()).spanned().collect::<Vec<_>>();
+        let json = line(&tokens, None, None, Some("oops"));
+        assert_eq!(json, concat!(
+            r#"{"tokens":[{"token":{"Num":1},"span":{"start":0,"end":1}},"#,
+            r#"{"token":"Plus","span":{"start":2,"end":3}}],"#,
+            r#""ast":null,"value":null,"error":"oops"}"#,
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 use std::iter::{Iterator, Peekable};
 
-use crate::token::Token;
+use crate::token::{Token, Span};
 
 static WHITES: &'static [char] = &[
     9 as char,
@@ -16,36 +16,73 @@ impl <T: Iterator<Item=char>> CharIterator for T {}
 
 #[derive(Clone)]
 pub struct Lexer<T: CharIterator> {
-    feed: Peekable<T>
+    feed: Peekable<T>,
+    start: usize,
+    end: usize,
+    pos: usize,
+    /// The `..` or `..=` read right after an integer, as in `1..5`.
+    pending: Option<Token>,
+    lenient: bool
+}
+
+/// Lexer that yields each token together with its span.
+#[derive(Clone)]
+pub struct Spanned<T: CharIterator> {
+    lexer: Lexer<T>
 }
 
 impl <T: CharIterator> Lexer<T> {
     pub fn new(feed: T) -> Self {
         Self {
-            feed: feed.peekable()
+            feed: feed.peekable(),
+            start: 0,
+            end: 0,
+            pos: 0,
+            pending: None,
+            lenient: false
+        }
+    }
+    /// Yield `Token::Unknown` for characters it cannot read instead of
+    /// panicking, for input that does not come from the program itself.
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+    pub fn spanned(self) -> Spanned<T> {
+        Spanned {
+            lexer: self
         }
     }
+    fn bump(&mut self) {
+        self.feed.next();
+        self.pos += 1;
+    }
     fn skip_these(&mut self, chk: &[char]) {
         while let Some(c) = self.feed.peek() {
             if !chk.contains(c) {
                 return
             }
-            self.feed.next();
+            self.bump();
         }
     }
     fn expect(&mut self, c: char) -> bool {
         if let Some(&nc) = self.feed.peek() {
             if nc == c {
-                self.feed.next();
+                self.bump();
                 return true
             }
         }
         false
     }
+    /// `..` or `..=`, after the two dots.
+    fn dots(&mut self) -> Token {
+        if self.expect('=') { Token::DotDotEqual }
+        else                { Token::DotDot }
+    }
     fn expect_these(&mut self, chk: &[char]) -> Option<char> {
         if let Some(&nc) = self.feed.peek() {
             if chk.contains(&nc) {
-                self.feed.next();
+                self.bump();
                 return Some(nc)
             }
         }
@@ -54,7 +91,7 @@ impl <T: CharIterator> Lexer<T> {
     fn expect_pred<F>(&mut self, f: F) -> Option<char> where F: Fn(&char) -> bool {
         if let Some(&nc) = self.feed.peek() {
             if f(&nc) {
-                self.feed.next();
+                self.bump();
                 return Some(nc)
             }
         }
@@ -65,15 +102,19 @@ impl <T: CharIterator> Lexer<T> {
 fn is_name_first(c: &char) -> bool { c.is_ascii_alphabetic()   || *c == '_' }
 fn is_name      (c: &char) -> bool { c.is_ascii_alphanumeric() || *c == '_' }
 
-fn to_digit(c: char) -> i64 { let num = c as u8 - '0' as u8; num as i64 }
-
 impl <T: CharIterator> Iterator for Lexer<T> {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tok) = self.pending.take() {
+            self.start = self.end;
+            self.end = self.pos;
+            return Some(tok)
+        }
         self.skip_these(WHITES);
+        self.start = self.pos;
 
         let tok = 
-        if let Some(..) = self.expect_these(LINES) {
+        if self.expect_these(LINES).is_some() {
             self.skip_these(LINES);
             Token::Line
         }
@@ -85,13 +126,43 @@ impl <T: CharIterator> Iterator for Lexer<T> {
             Token::Name(s)
         }
         else if let Some(c) = self.expect_pred(char::is_ascii_digit) {
-            let mut n = to_digit(c);
+            let mut s = String::from(c);
             while let Some(c) = self.expect_pred(char::is_ascii_digit) {
-                n = n * 10 + to_digit(c);
+                s.push(c)
+            }
+            let int = |s: String| match s.parse() {
+                Ok(n) => Token::Num(n),
+                Err(..) => Token::Malformed(s, "integer too large"),
+            };
+            if self.expect('.') {
+                if self.expect('.') {
+                    // the range `1..5`, not the float `1.` and then `.5`
+                    let start = self.pos - 2;
+                    self.pending = Some(self.dots());
+                    self.end = start;
+                    return Some(int(s))
+                }
+                s.push('.');
+                while let Some(c) = self.expect_pred(char::is_ascii_digit) {
+                    s.push(c)
+                }
+                let f = s.parse().unwrap();
+                match self.expect('i') {
+                    true => Token::Imag(f),
+                    false => Token::Float(f),
+                }
+            }
+            else if self.expect('i') {
+                Token::Imag(s.parse().unwrap())
+            }
+            else {
+                int(s)
             }
-            Token::Num(n)
         }
-        else if self.expect('+') { Token::Plus }
+        else if self.expect('+') {
+            if self.expect('+') { Token::PlusPlus }
+            else                { Token::Plus }
+        }
         else if self.expect('-') { Token::Dash }
 
         else if self.expect('*') {
@@ -101,28 +172,129 @@ impl <T: CharIterator> Iterator for Lexer<T> {
         else if self.expect('/') { Token::Slash }
         else if self.expect('%') { Token::Percent }
 
-        else if self.expect('&') { Token::And }
-        else if self.expect('|') { Token::Pipe }
+        else if self.expect('&') {
+            if self.expect('&') { Token::AndAnd }
+            else                { Token::And }
+        }
+        else if self.expect('|') {
+            if self.expect('|') { Token::PipePipe }
+            else                { Token::Pipe }
+        }
         else if self.expect('^') { Token::Hat }
         else if self.expect('~') { Token::Worm }
-        else if self.expect('!') { Token::Exc }
+        else if self.expect('!') {
+            if self.expect('=') { Token::ExcEqual }
+            else                { Token::Exc }
+        }
 
         else if self.expect('<') {
-            if self.expect('<') { Token::LessLess }
-            else                { todo!() }
+            if self.expect('<')      { Token::LessLess }
+            else if self.expect('=') { Token::LessEqual }
+            else                     { Token::Less }
         }
         else if self.expect('>') {
-            if self.expect('>') { Token::MoreMore }
-            else                { todo!() }
+            if self.expect('>')      { Token::MoreMore }
+            else if self.expect('=') { Token::MoreEqual }
+            else                     { Token::More }
         }
         else if self.expect('(') { Token::OpenParen }
         else if self.expect(')') { Token::CloseParen }
+        else if self.expect('[') { Token::OpenBracket }
+        else if self.expect(']') { Token::CloseBracket }
+
+        else if self.expect(',') { Token::Comma }
+        else if self.expect('.') {
+            if self.expect('.') { self.dots() }
+            else                { Token::Dot }
+        }
+        else if self.expect(':') {
+            if self.expect(':') { Token::ColonColon }
+            else                { Token::Colon }
+        }
+        else if self.expect('?') { Token::Question }
+
+        else if self.expect('"') {
+            let mut s = String::new();
+            while let Some(c) = self.expect_pred(|&c| c != '"') {
+                s.push(c)
+            }
+            match self.expect('"') {
+                true => Token::Str(s),
+                false => Token::Malformed(format!("\"{}", s), "unterminated string"),
+            }
+        }
+
+        else if self.expect('=') {
+            if self.expect('=') { Token::EqualEqual }
+            else                { Token::Equal }
+        }
 
-        else if let Some(c) = self.feed.peek() {
-            panic!("unknow character {}", c)
+        else if let Some(&c) = self.feed.peek() {
+            if !self.lenient {
+                panic!("unknow character {}", c)
+            }
+            self.bump();
+            Token::Unknown(c)
         }
         else { return None };
 
+        self.end = self.pos;
         Some(tok)
     }
 }
+
+impl <T: CharIterator> Iterator for Spanned<T> {
+    type Item = (Token, Span);
+    fn next(&mut self) -> Option<Self::Item> {
+        let tok = self.lexer.next()?;
+        let span = Span {
+            start: self.lexer.start,
+            end: self.lexer.end
+        };
+        Some((tok, span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(src: &str) -> Vec<String> {
+        Lexer::new(src.chars())
+            .lenient()
+            .map(|tok| match tok {
+                Token::Malformed(text, why) => format!("<{}: {}>", why, text),
+                tok => tok.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_numbers() {
+        assert_eq!(lex("0 42 1.5 2. 0.1 3i 2.5i"), ["0", "42", "1.5", "2", "0.1", "3i", "2.5i"]);
+        assert_eq!(lex("9223372036854775807"), ["9223372036854775807"]);
+        assert_eq!(lex("9223372036854775808 + 1"), ["<integer too large: 9223372036854775808>", "+", "1"]);
+        assert_eq!(lex("123456789012345678901234567890.5"), ["123456789012345680000000000000"]);
+    }
+
+    #[test]
+    fn spans_tokens() {
+        let spans = Lexer::new("10 + x2".chars()).spanned().map(|(_, span)| (span.start, span.end)).collect::<Vec<_>>();
+        assert_eq!(spans, [(0, 2), (3, 4), (5, 7)]);
+    }
+
+    #[test]
+    fn splits_ranges_from_floats() {
+        assert_eq!(lex("1..5 1..=n 1.5"), ["1", "..", "5", "1", "..=", "n", "1.5"]);
+        let spans = Lexer::new("10..20".chars()).spanned().map(|(_, span)| (span.start, span.end)).collect::<Vec<_>>();
+        assert_eq!(spans, [(0, 2), (2, 4), (4, 6)]);
+    }
+
+    #[test]
+    fn reads_operators_names_and_strings() {
+        assert_eq!(lex("a_1 ** -b << \"s t\" c!"), ["a_1", "**", "-", "b", "<<", "\"s t\"", "c", "!"]);
+        assert_eq!(lex("[a] ++ b[0]"), ["[", "a", "]", "++", "b", "[", "0", "]"]);
+        assert_eq!(lex("x $ y"), ["x", "$", "y"]);
+        assert_eq!(lex("\"\" \"abc"), ["\"\"", "<unterminated string: \"abc>"]);
+    }
+}
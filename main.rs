@@ -1,63 +1,394 @@
-#![allow(incomplete_features)]
-#![feature(if_let_guard)]
-#![feature(let_chains)]
+#![allow(clippy::useless_format, clippy::redundant_static_lifetimes, clippy::needless_return)]
 
 mod node;
 mod token;
+mod range;
+mod matrix;
+mod complex;
+
+mod env;
+mod eval;
+mod partial;
+mod module;
+
+mod visit;
+mod fold;
+
+mod infix;
+mod sexpr;
+mod json;
+mod dot;
+mod render;
+
+mod builtin;
+mod vm;
+mod bench;
+
+mod settings;
+mod cli;
+mod command;
+mod editor;
+mod complete;
+mod highlight;
 
 mod lexer;
 mod parser;
 
-use std::io::{stdin, stdout, Write};
+use std::fs::File;
+use std::io::{self, stdin, BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use lexer::Lexer;
-use node::Node;
+use cli::{Options, Format, Show};
+use complete::Completer;
+use editor::{Candidate, Editor, Helper, Input};
+use env::Env;
+use eval::EvalError;
+use lexer::{Lexer, CharIterator};
+use node::{Node, Stmt, Value, PrefixOp, BinaryOp};
 use parser::{Parser, Result};
+use token::Token;
 
 static LINES: &'static [char] = &[13 as char, 10 as char];
 
-fn main() {
-    loop {
-        print!("input> ");
-        stdout()
-            .flush()
-            .unwrap();
+/// What the line editor knows of the session: names for completion, and
+/// `pending` lines of an incomplete statement for the hint.
+struct Repl<'a> {
+    env: &'a Env,
+    pending: &'a str
+}
 
-        let mut line = String::new();
-        stdin()
-            .read_line(&mut line)
-            .expect("read line failed");
+impl Helper for Repl<'_> {
+    fn complete(&self, line: &str, pos: usize) -> (usize, Vec<Candidate>) {
+        Completer::new(self.env).complete(line, pos)
+    }
+    fn highlight(&self, line: &str, pos: usize) -> String {
+        match line.starts_with(':') && self.pending.is_empty() {
+            true => line.to_string(),
+            false => highlight::highlight(line, pos),
+        }
+    }
+    fn hint(&self, line: &str) -> Option<String> {
+        match self.pending.is_empty() {
+            true if line.starts_with(':') => None,
+            true => highlight::hint(self.env, &with_ans(self.env, line)),
+            false => highlight::hint(self.env, &format!("{} {}", self.pending, line)),
+        }
+    }
+}
+
+enum Outcome {
+    Empty,
+    Value(Stmt, Value),
+    Done(Stmt),
+    ParseError(String),
+    EvalError(Stmt, EvalError),
+}
+
+fn main() -> ExitCode {
+    let opts = match cli::parse(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprint!("{}", cli::USAGE);
+            return ExitCode::from(cli::EXIT_USAGE)
+        }
+    };
+    if opts.help {
+        print!("{}", cli::USAGE);
+        return ExitCode::SUCCESS
+    }
+
+    let mut env = Env::new();
+    if let Some(radix) = opts.radix {
+        env.settings.radix = radix
+    }
+    env.settings.precision = opts.precision;
+    env.modules.search.extend(opts.search.iter().map(PathBuf::from));
+    if let Some(path) = &opts.session {
+        if Path::new(path).exists() {
+            if let Err(err) = command::load(&mut env, path) {
+                eprintln!("error: {}", err);
+                return ExitCode::from(cli::EXIT_IO)
+            }
+        }
+        env.settings.autosave = Some(path.clone())
+    }
+
+    if !opts.exprs.is_empty() {
+        let lines = opts.exprs
+            .iter()
+            .cloned()
+            .map(Ok);
+        return batch(&mut env, lines, &opts)
+    }
+    match &opts.path {
+        Some(path) if path != "-" => match File::open(path) {
+            Ok(file) => {
+                // imports in the script resolve relative to it
+                if let Err(err) = env.modules.enter(Path::new(path)) {
+                    eprintln!("error: {}", err);
+                    return ExitCode::from(cli::EXIT_IO)
+                }
+                batch(&mut env, BufReader::new(file).lines(), &opts)
+            }
+            Err(err) => {
+                eprintln!("error: {}: {}", path, err);
+                ExitCode::from(cli::EXIT_IO)
+            }
+        },
+        Some(..) => batch(&mut env, stdin().lock().lines(), &opts),
+        None if opts.bench.is_some() || !stdin().is_terminal() => batch(&mut env, stdin().lock().lines(), &opts),
+        None => {
+            repl(&mut env, &opts);
+            ExitCode::SUCCESS
+        }
+    }
+}
 
+/// Evaluate every line of `input`, printing what `--show` asks for (only
+/// results by default). Stops at the first error unless `--keep-going`, and
+/// exits with the status of the first error.
+fn batch(env: &mut Env, input: impl Iterator<Item=io::Result<String>>, opts: &Options) -> ExitCode {
+    let show = opts.show.unwrap_or(Show::value());
+    let mut status = None;
+    for (index, line) in input.enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return ExitCode::from(cli::EXIT_IO)
+            }
+        };
         let inp = line.trim_end_matches(LINES);
         if inp == "." {
-            return;
+            break;
         }
 
+        let lineno = index + 1;
+        let code = if let Some(cmd) = inp.strip_prefix(':') {
+            match command::run(env, cmd) {
+                Ok(()) => None,
+                Err(err) => {
+                    eprintln!("error: line {}: {}", lineno, err);
+                    Some(cli::EXIT_PARSE)
+                }
+            }
+        }
+        else if let Some(rows) = opts.bench {
+            match Parser::new(Lexer::new(inp.chars()).lenient()).parse() {
+                Result::None => None,
+                Result::Ok(node) => match bench::compare(&node, rows, env.settings.mode) {
+                    Ok(report) => {
+                        println!("{}", inp);
+                        println!("{}", report);
+                        None
+                    }
+                    Err(err) => {
+                        eprintln!("error: line {}: {}", lineno, err);
+                        Some(cli::EXIT_EVAL)
+                    }
+                },
+                Result::Err(err) | Result::Incomplete(err) => {
+                    eprintln!("error: line {}: {}", lineno, err);
+                    Some(cli::EXIT_PARSE)
+                }
+            }
+        }
+        else {
+            let inp = with_ans(env, inp);
+            let toks = Lexer::new(inp.chars()).lenient();
+            let outcome = match opts.format {
+                Format::Json => {
+                    let (line, outcome) = eval_json(env, toks);
+                    println!("{}", line);
+                    outcome
+                }
+                Format::Plain => {
+                    let outcome = eval(env, toks.clone());
+                    print_outcome(env, &toks, &outcome, show, false);
+                    outcome
+                }
+            };
+            match (outcome, opts.format) {
+                (Outcome::ParseError(..), Format::Json) => Some(cli::EXIT_PARSE),
+                (Outcome::EvalError(..), Format::Json) => Some(cli::EXIT_EVAL),
+                (Outcome::ParseError(err), Format::Plain) => {
+                    eprintln!("error: line {}: {}", lineno, err);
+                    Some(cli::EXIT_PARSE)
+                }
+                (Outcome::EvalError(_, err), Format::Plain) => {
+                    eprintln!("error: line {}: {}", lineno, err);
+                    Some(cli::EXIT_EVAL)
+                }
+                _ => None,
+            }
+        };
+        if let Some(code) = code {
+            status = status.or(Some(code));
+            if !opts.keep_going {
+                break;
+            }
+        }
+    }
+    match status {
+        Some(code) => ExitCode::from(code),
+        None => ExitCode::SUCCESS,
+    }
+}
+
+fn repl(env: &mut Env, opts: &Options) {
+    let show = opts.show.unwrap_or(Show::value());
+    let json = matches!(opts.format, Format::Json);
+    let mut editor = Editor::new(opts.keymap);
+    let mut pending = String::new();
+    loop {
+        let prompt = match (json, pending.is_empty()) {
+            (true, _) => "",
+            (false, true) => "input> ",
+            (false, false) => "...> ",
+        };
+        let line = match editor.read_line(prompt, &Repl { env, pending: &pending }).expect("read line failed") {
+            Input::Line(line) => line,
+            Input::Cancel => {
+                pending.clear();
+                continue
+            }
+            Input::Eof => return,
+        };
+
+        let inp = line.trim_end_matches(LINES);
+        if pending.is_empty() {
+            if inp == "." {
+                return;
+            }
+            if let Some(cmd) = inp.strip_prefix(':') {
+                if let Err(err) = command::run(env, cmd).and_then(|()| command::autosave(env)) {
+                    println!("error: {}", err)
+                }
+                continue;
+            }
+        }
+        else {
+            pending.push(' ');
+        }
+
+        // keep reading lines while the input so far only lacks its end
+        match pending.is_empty() {
+            true => pending.push_str(&with_ans(env, inp)),
+            false => pending.push_str(inp),
+        }
+        if let Result::Incomplete(..) = Parser::new(Lexer::new(pending.chars()).lenient()).parse_stmt() {
+            continue;
+        }
+        let inp = std::mem::take(&mut pending);
+
         let chars = inp.chars();
-        let toks = Lexer::new(chars);
+        let toks = Lexer::new(chars).lenient();
 
+        if json {
+            println!("{}", eval_json(env, toks).0);
+        }
+        else {
+            let outcome = eval(env, toks.clone());
+            print_outcome(env, &toks, &outcome, show, true);
+            match outcome {
+                Outcome::EvalError(stmt, err) => {
+                    println!("error: {}", err);
+                    if let (Stmt::Expr(node), EvalError::Unbound(..)) = (stmt, err) {
+                        println!("partial: {}", node.partial_eval(env).infix())
+                    }
+                }
+                Outcome::ParseError(err) => println!("error: {}", err),
+                _ => {}
+            }
+        }
+        if let Err(err) = command::autosave(env) {
+            println!("error: {}", err)
+        }
+    }
+}
+
+/// Print what `show` asks for. A `numbered` value is labelled with the
+/// `_N` name it can be referred to by.
+fn print_outcome<T: CharIterator + Clone>(env: &Env, toks: &Lexer<T>, outcome: &Outcome, show: Show, numbered: bool) {
+    if show.tokens {
         print!("tokens: ");
         toks.clone()
             .for_each(
                 |tok| print!("{} ", tok)
             );
         println!();
+    }
+    if let Outcome::Value(stmt, _) | Outcome::Done(stmt) | Outcome::EvalError(stmt, _) = outcome {
+        if show.ast {
+            println!("nodes: {}", stmt)
+        }
+        if let (true, Some(node)) = (show.tree, stmt.node()) {
+            print(node)
+        }
+    }
+    if let (Outcome::Value(_, val), true) = (outcome, show.value) {
+        if show.bare() && numbered {
+            println!("_{} = {}", env.result_count(), env.settings.show(val))
+        }
+        else if show.bare() {
+            println!("{}", env.settings.show(val))
+        }
+        else {
+            println!("eval: {}", env.settings.show(val))
+        }
+    }
+}
+
+/// A line starting with a binary operator that cannot be a prefix, like
+/// `* 2`, continues from the last result: `ans * 2`. `|` and `||` start
+/// lambdas instead.
+fn with_ans(env: &Env, inp: &str) -> String {
+    let first = Lexer::new(inp.chars())
+        .lenient()
+        .next();
+    match first {
+        Some(tok) if env.result_count() > 0
+            && Option::<BinaryOp>::from(&tok).is_some()
+            && Option::<PrefixOp>::from(&tok).is_none()
+            && !matches!(tok, Token::Pipe | Token::PipePipe) => format!("ans {}", inp),
+        _ => inp.to_string(),
+    }
+}
 
-        let pars = Parser::new(toks).parse();
-        match pars {
-            Result::None => println!("empty"),
-            Result::Ok(node) => {
-                let node_view = node.clone();
-                println!("nodes: {}", node_view);
-                print(node_view);
-                println!("eval: {}", node.eval())
+fn eval<T: CharIterator>(env: &mut Env, toks: Lexer<T>) -> Outcome {
+    match Parser::new(toks).parse_stmt() {
+        Result::None => Outcome::Empty,
+        Result::Ok(stmt) => match stmt.exec(env) {
+            Ok(Some(val)) => {
+                env.push_result(val.clone());
+                Outcome::Value(stmt, val)
             }
-            Result::Err(err) => println!("error: {}", err),
-        }
+            Ok(None) => Outcome::Done(stmt),
+            Err(err) => Outcome::EvalError(stmt, err),
+        },
+        Result::Err(err) | Result::Incomplete(err) => Outcome::ParseError(err),
     }
 }
 
-fn print(ref node: Node) {
+fn eval_json<T: CharIterator + Clone>(env: &mut Env, toks: Lexer<T>) -> (String, Outcome) {
+    let tokens = toks
+        .clone()
+        .spanned()
+        .collect::<Vec<_>>();
+    let outcome = eval(env, toks);
+    let line = match &outcome {
+        Outcome::Empty => json::line(&tokens, None, None, None),
+        Outcome::Value(stmt, val) => json::line(&tokens, Some(stmt), Some(val), None),
+        Outcome::Done(stmt) => json::line(&tokens, Some(stmt), None, None),
+        Outcome::ParseError(err) => json::line(&tokens, None, None, Some(err)),
+        Outcome::EvalError(stmt, err) => json::line(&tokens, Some(stmt), None, Some(&err.to_string())),
+    };
+    (line, outcome)
+}
+
+fn print(node: &Node) {
     fn print_branch(last_level: bool, last_item: bool) {
         if last_level {
             if last_item {
@@ -97,6 +428,42 @@ fn print(ref node: Node) {
                 print_tree_inner(left, path, false);
                 print_tree_inner(right, path, true)
             }
+            Node::Call(name, args) => {
+                println!("Call({})", name);
+                let last_index = args.len().saturating_sub(1);
+                args
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, arg)| print_tree_inner(arg, path, i == last_index))
+            }
+            Node::If(cond, then, other) => {
+                println!("If");
+                print_tree_inner(cond, path, false);
+                print_tree_inner(then, path, false);
+                print_tree_inner(other, path, true)
+            }
+            Node::For(var, seq, body) => {
+                println!("For({})", var);
+                print_tree_inner(seq, path, false);
+                print_tree_inner(body, path, true)
+            }
+            Node::List(items) => {
+                println!("List");
+                let last_index = items.len().saturating_sub(1);
+                items
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, item)| print_tree_inner(item, path, i == last_index))
+            }
+            Node::Index(node, index) => {
+                println!("Index");
+                print_tree_inner(node, path, false);
+                print_tree_inner(index, path, true)
+            }
+            Node::Lambda(params, body) => {
+                println!("Lambda({})", params.join(", "));
+                print_tree_inner(body, path, true)
+            }
         }
 
         path.pop();
@@ -105,3 +472,59 @@ fn print(ref node: Node) {
     let mut path: Vec<bool> = Vec::new();
     print_tree_inner(node, &mut path, true);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], lines: &[&str]) -> (ExitCode, Env) {
+        let opts = cli::parse(args.iter().map(|arg| arg.to_string())).ok().unwrap();
+        let mut env = Env::new();
+        let code = batch(&mut env, lines.iter().map(|line| Ok(line.to_string())), &opts);
+        (code, env)
+    }
+
+    #[test]
+    fn batch_stops_at_the_first_error() {
+        let (code, env) = run(&[], &["x = 1", "1 / 0", "y = 2"]);
+        assert_eq!(code, ExitCode::from(cli::EXIT_EVAL));
+        assert!(env.get("x").is_some());
+        assert!(env.get("y").is_none());
+        assert_eq!(run(&[], &["1", ".", "1 / 0"]).0, ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn batch_keeps_going_with_the_first_status() {
+        let (code, env) = run(&["--keep-going"], &["1 $ 2", "1 / 0", "y = 2"]);
+        assert_eq!(code, ExitCode::from(cli::EXIT_PARSE));
+        assert!(env.get("y").is_some());
+    }
+
+    #[test]
+    fn batch_reports_unknown_characters_as_parse_errors() {
+        for line in ["1 $ 2", "$", "(1 + 2", "f(1, 2 @)", "\"abc"] {
+            assert_eq!(run(&[], &[line]).0, ExitCode::from(cli::EXIT_PARSE), "{}", line);
+            assert_eq!(run(&["--json"], &[line]).0, ExitCode::from(cli::EXIT_PARSE), "{}", line);
+        }
+        assert_eq!(run(&["--bench", "10"], &["x $ 2"]).0, ExitCode::from(cli::EXIT_PARSE));
+    }
+
+    #[test]
+    fn batch_continues_from_ans() {
+        let (code, env) = run(&[], &["6", "* 7", "x = _1 + ans"]);
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(env.get("x").map(Value::to_string).as_deref(), Some("48"));
+    }
+
+    #[test]
+    fn leading_operators_continue_from_ans() {
+        let mut env = Env::new();
+        assert_eq!(with_ans(&env, "* 2"), "* 2");
+        env.push_result(Value::Num(3));
+        assert_eq!(with_ans(&env, "* 2"), "ans * 2");
+        assert_eq!(with_ans(&env, "== 3"), "ans == 3");
+        for line in ["-1", "!x", "~x", "|x| x", "|| 1", "x * 2", ""] {
+            assert_eq!(with_ans(&env, line), line);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+
+use crate::eval::{EvalError, MAX_ITERATIONS};
+use crate::node::{Value, BinaryOp};
+
+/// Numbers in `rows` rows of `cols` columns, stored row after row.
+#[derive(Clone)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub items: Vec<Value>
+}
+
+/// Rows and columns of a matrix. A list multiplied with a matrix counts as
+/// a single column, or a single row on the left.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Shape(pub usize, pub usize);
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.0, self.1)
+    }
+}
+
+// pivots smaller than this, relative to the largest entry, count as zero
+const EPSILON: f64 = 1e-12;
+
+impl Matrix {
+    /// `[[1, 2], [3, 4]]`: lists of numbers, all of the same length.
+    pub fn from_rows(rows: &[Value]) -> Option<Self> {
+        let cols = match rows.first()? {
+            Value::List(row) if !row.is_empty() => row.len(),
+            _ => return None,
+        };
+        let mut items = Vec::with_capacity(rows.len() * cols);
+        for row in rows {
+            match row {
+                Value::List(row) if row.len() == cols => items.extend(row.iter().cloned()),
+                _ => return None,
+            }
+        }
+        match items.iter().all(|item| matches!(item, Value::Num(..) | Value::Float(..))) {
+            true => Some(Self { rows: rows.len(), cols, items }),
+            false => None,
+        }
+    }
+    /// A matrix, a list of rows, or a list of numbers as a single row.
+    pub fn from_value(val: &Value) -> Option<Self> {
+        match val {
+            Value::Matrix(matrix) => Some(matrix.clone()),
+            Value::List(items) => Self::from_rows(items).or_else(|| Self::from_rows(std::slice::from_ref(val))),
+            _ => None,
+        }
+    }
+    /// A list of numbers as a single column.
+    pub fn column(items: Vec<Value>) -> Self {
+        Self { rows: items.len(), cols: 1, items }
+    }
+    pub fn identity(n: usize) -> Result<Self, EvalError> {
+        if n.checked_mul(n).is_none_or(|len| len > MAX_ITERATIONS) {
+            return Err(EvalError::TooLarge(Shape(n, n)))
+        }
+        let items = (0..n * n)
+            .map(|i| Value::Num((i / n == i % n) as i64))
+            .collect();
+        Ok(Self { rows: n, cols: n, items })
+    }
+    pub fn shape(&self) -> Shape {
+        Shape(self.rows, self.cols)
+    }
+    pub fn row(&self, i: usize) -> Value {
+        Value::List(self.items[i * self.cols..(i + 1) * self.cols].to_vec())
+    }
+    pub fn rows(&self) -> impl Iterator<Item = Value> + '_ {
+        (0..self.rows).map(|i| self.row(i))
+    }
+    /// The rows of `self` followed by those of `other`, which must have
+    /// as many columns.
+    pub fn concat(mut self, other: Self) -> Result<Self, EvalError> {
+        if self.cols != other.cols {
+            return Err(EvalError::Shape(self.shape(), other.shape()))
+        }
+        self.rows += other.rows;
+        self.items.extend(other.items);
+        Ok(self)
+    }
+    pub fn map(self, f: &dyn Fn(Value) -> Result<Value, EvalError>) -> Result<Self, EvalError> {
+        let items = self.items
+            .into_iter()
+            .map(f)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { items, ..self })
+    }
+    /// `f` of the items at the same place in both, which must be the same
+    /// shape.
+    pub fn zip(self, other: Self, f: &dyn Fn(Value, Value) -> Result<Value, EvalError>) -> Result<Self, EvalError> {
+        if self.shape() != other.shape() {
+            return Err(EvalError::Shape(self.shape(), other.shape()))
+        }
+        let items = self.items
+            .into_iter()
+            .zip(other.items)
+            .map(|(left, right)| f(left, right))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { items, ..self })
+    }
+    /// Matrix product, with `f` multiplying items.
+    pub fn mul(&self, other: &Self, f: &dyn Fn(Value, Value) -> Result<Value, EvalError>) -> Result<Self, EvalError> {
+        if self.cols != other.rows {
+            return Err(EvalError::Shape(self.shape(), other.shape()))
+        }
+        let mut items = Vec::with_capacity(self.rows * other.cols);
+        for i in 0..self.rows {
+            for j in 0..other.cols {
+                let mut sum = Value::Num(0);
+                for k in 0..self.cols {
+                    let product = f(self.items[i * self.cols + k].clone(), other.items[k * other.cols + j].clone())?;
+                    sum = BinaryOp::Add.apply(sum, product)?;
+                }
+                items.push(sum)
+            }
+        }
+        Ok(Self { rows: self.rows, cols: other.cols, items })
+    }
+    pub fn transpose(&self) -> Self {
+        let items = (0..self.rows * self.cols)
+            .map(|i| self.items[(i % self.rows) * self.cols + i / self.rows].clone())
+            .collect();
+        Self { rows: self.cols, cols: self.rows, items }
+    }
+    fn square(&self) -> Result<usize, EvalError> {
+        match self.rows == self.cols {
+            true => Ok(self.rows),
+            false => Err(EvalError::NotSquare(self.shape())),
+        }
+    }
+    fn ints(&self) -> Option<Vec<i64>> {
+        self.items
+            .iter()
+            .map(|item| match item {
+                Value::Num(n) => Some(*n),
+                _ => None,
+            })
+            .collect()
+    }
+    fn floats(&self) -> Result<Vec<f64>, EvalError> {
+        self.items
+            .iter()
+            .map(|item| match item {
+                Value::Num(n) => Ok(*n as f64),
+                Value::Float(x) => Ok(*x),
+                item => Err(EvalError::Unsupported(format!("matrix item {}", item))),
+            })
+            .collect()
+    }
+    /// Exact for integers, as long as the steps fit in `i64`.
+    pub fn det(&self) -> Result<Value, EvalError> {
+        let n = self.square()?;
+        if let Some(det) = self.ints().and_then(|items| det_exact(n, items)) {
+            return Ok(Value::Num(det))
+        }
+        let mut items = self.floats()?;
+        let mut det = 1.0;
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&a, &b| items[a * n + k].abs().total_cmp(&items[b * n + k].abs()))
+                .unwrap();
+            if items[pivot * n + k] == 0.0 {
+                return Ok(Value::Float(0.0))
+            }
+            if pivot != k {
+                swap_rows(&mut items, n, pivot, k);
+                det = -det
+            }
+            det *= items[k * n + k];
+            for i in k + 1..n {
+                let factor = items[i * n + k] / items[k * n + k];
+                for j in k..n {
+                    items[i * n + j] -= factor * items[k * n + j]
+                }
+            }
+        }
+        Ok(Value::Float(det))
+    }
+    pub fn inverse(&self) -> Result<Self, EvalError> {
+        let n = self.square()?;
+        self.solve(&Self::identity(n)?)
+    }
+    /// `x` such that `self * x` is `b`, by Gauss-Jordan elimination. Exact
+    /// up to the last division for integers, as long as the steps fit.
+    pub fn solve(&self, b: &Self) -> Result<Self, EvalError> {
+        let n = self.square()?;
+        if b.rows != n {
+            return Err(EvalError::Shape(self.shape(), b.shape()))
+        }
+        let m = b.cols;
+        if let (Some(a), Some(x)) = (self.ints(), b.ints()) {
+            if let Some(x) = solve_exact(n, a, m, x) {
+                return Ok(Self { rows: n, cols: m, items: x?.into_iter().map(Value::Float).collect() })
+            }
+        }
+        let (mut a, mut x) = (self.floats()?, b.floats()?);
+        let scale = a.iter().fold(0.0, |max: f64, item| max.max(item.abs()));
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&p, &q| a[p * n + k].abs().total_cmp(&a[q * n + k].abs()))
+                .unwrap();
+            let largest = a[pivot * n + k].abs();
+            if largest <= EPSILON * scale || largest.is_nan() {
+                return Err(EvalError::Singular)
+            }
+            swap_rows(&mut a, n, pivot, k);
+            swap_rows(&mut x, m, pivot, k);
+            let div = a[k * n + k];
+            a[k * n..(k + 1) * n].iter_mut().for_each(|item| *item /= div);
+            x[k * m..(k + 1) * m].iter_mut().for_each(|item| *item /= div);
+            for i in (0..n).filter(|&i| i != k) {
+                let factor = a[i * n + k];
+                for j in 0..n {
+                    a[i * n + j] -= factor * a[k * n + j]
+                }
+                for j in 0..m {
+                    x[i * m + j] -= factor * x[k * m + j]
+                }
+            }
+        }
+        Ok(Self { rows: n, cols: m, items: x.into_iter().map(Value::Float).collect() })
+    }
+}
+
+fn swap_rows(items: &mut [f64], cols: usize, a: usize, b: usize) {
+    for j in 0..cols {
+        items.swap(a * cols + j, b * cols + j)
+    }
+}
+
+/// Bareiss elimination, whose divisions are all exact.
+fn det_exact(n: usize, mut items: Vec<i64>) -> Option<i64> {
+    let (mut sign, mut prev) = (1, 1i128);
+    for k in 0..n - 1 {
+        if items[k * n + k] == 0 {
+            let pivot = match (k + 1..n).find(|&i| items[i * n + k] != 0) {
+                Some(pivot) => pivot,
+                None => return Some(0),
+            };
+            for j in 0..n {
+                items.swap(pivot * n + j, k * n + j)
+            }
+            sign = -sign
+        }
+        let diag = items[k * n + k] as i128;
+        for i in k + 1..n {
+            for j in k + 1..n {
+                let (a, b) = (items[i * n + j] as i128 * diag, items[i * n + k] as i128 * items[k * n + j] as i128);
+                items[i * n + j] = i64::try_from(a.checked_sub(b)? / prev).ok()?
+            }
+        }
+        prev = diag
+    }
+    items[n * n - 1].checked_mul(sign)
+}
+
+/// Fraction-free Gauss-Jordan elimination of `a | b`, which leaves the
+/// determinant on the diagonal of `a`. `None` when a step overflows.
+fn solve_exact(n: usize, a: Vec<i64>, m: usize, b: Vec<i64>) -> Option<Result<Vec<f64>, EvalError>> {
+    let cols = n + m;
+    let mut rows = (0..n)
+        .flat_map(|i| a[i * n..(i + 1) * n].iter().chain(&b[i * m..(i + 1) * m]))
+        .map(|&item| item as i128)
+        .collect::<Vec<_>>();
+    let mut prev = 1i128;
+    for k in 0..n {
+        let pivot = match (k..n).find(|&i| rows[i * cols + k] != 0) {
+            Some(pivot) => pivot,
+            None => return Some(Err(EvalError::Singular)),
+        };
+        for j in 0..cols {
+            rows.swap(pivot * cols + j, k * cols + j)
+        }
+        let diag = rows[k * cols + k];
+        for i in (0..n).filter(|&i| i != k) {
+            let factor = rows[i * cols + k];
+            for j in 0..cols {
+                let a = rows[i * cols + j].checked_mul(diag)?;
+                let b = factor.checked_mul(rows[k * cols + j])?;
+                let next = a.checked_sub(b)?;
+                if next % prev != 0 {
+                    return None
+                }
+                rows[i * cols + j] = next / prev
+            }
+        }
+        prev = diag
+    }
+    let x = (0..n * m)
+        .map(|i| rows[(i / m) * cols + n + i % m] as f64 / rows[(i / m) * cols + i / m] as f64)
+        .collect();
+    Some(Ok(x))
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, row) in self.rows().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?
+            }
+            write!(f, "{}", row)?
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::Env;
+    use crate::eval::tests::run;
+
+    fn check(cases: &[(&str, &str)]) {
+        let mut env = Env::new();
+        for (src, res) in cases {
+            assert_eq!(run(&mut env, src), *res, "{}", src);
+        }
+    }
+
+    #[test]
+    fn determinants() {
+        check(&[
+            ("det([[1, 2], [3, 4]])", "-2"), ("det([[2]])", "2"), ("det([[0, 1], [1, 0]])", "-1"), ("det([[1, 2], [2, 4]])", "0"),
+            ("det([[2, 0, 1], [1, 3, 2], [1, 1, 2]])", "6"), ("det([[1.5, 2], [3, 4]])", "0.0"),
+            // steps that overflow i64 fall back to floats
+            ("det([[4611686018427387904, 0], [0, 4]])", "18446744073709552000.0"),
+            ("det([[1, 2, 3]])", "error: 1x3 matrix is not square"),
+        ]);
+    }
+
+    #[test]
+    fn inverses_and_solutions() {
+        check(&[
+            ("inverse([[1, 2], [3, 4]])", "[[-2.0, 1.0], [1.5, -0.5]]"), ("inverse([[2, 0], [0, 4]])", "[[0.5, 0.0], [0.0, 0.25]]"),
+            ("solve([[2, 0], [0, 4]], [2, 8])", "[1.0, 2.0]"), ("solve([[0, 1], [1, 0]], [3, 4])", "[4.0, 3.0]"),
+            ("solve([[1, 2], [3, 4]], [[1, 0], [0, 1]])", "[[-2.0, 1.0], [1.5, -0.5]]"),
+            ("inverse([[1, 2], [2, 4]])", "error: matrix is singular"), ("inverse([[1.0, 2], [2, 4]])", "error: matrix is singular"),
+            ("inverse([[1, 2, 3]])", "error: 1x3 matrix is not square"),
+            ("solve([[1, 2], [3, 4]], [1, 2, 3])", "error: shapes 2x2 and 3x1 do not match"),
+        ]);
+    }
+
+    #[test]
+    fn products_and_shapes() {
+        check(&[
+            ("[[1, 2], [3, 4]] * [[5], [6]]", "[[17], [39]]"), ("[[1, 2], [3, 4]] * [1, 1]", "[3, 7]"), ("[1, 1] * [[1, 2], [3, 4]]", "[4, 6]"),
+            ("[[1, 2], [3, 4]] + [[1, 1], [1, 1]]", "[[2, 3], [4, 5]]"), ("[[1, 2], [3, 4]] * 2", "[[2, 4], [6, 8]]"),
+            ("transpose([[1, 2, 3]])", "[[1], [2], [3]]"), ("shape([[1, 2, 3]])", "[1, 3]"), ("identity(2)", "[[1, 0], [0, 1]]"),
+            ("[[1, 2], [3, 4]][1]", "[3, 4]"), ("[[1, 2], [3, 4]][0][1]", "2"),
+            ("[[1, 2]] * [[1, 2]]", "error: shapes 1x2 and 1x2 do not match"),
+            ("[[1, 2], [3, 4]] + [[1, 1]]", "error: shapes 2x2 and 1x2 do not match"),
+            ("identity(100000)", "error: 100000x100000 matrix is too large"),
+        ]);
+    }
+
+    #[test]
+    fn concatenation_stacks_rows() {
+        check(&[
+            ("m = [[1, 2], [3, 4]]", "[[1, 2], [3, 4]]"),
+            ("m ++ [[5, 6]]", "[[1, 2], [3, 4], [5, 6]]"), ("m ++ [5, 6]", "[[1, 2], [3, 4], [5, 6]]"), ("[0, 0] ++ m", "[[0, 0], [1, 2], [3, 4]]"),
+            ("m ++ m", "[[1, 2], [3, 4], [1, 2], [3, 4]]"), ("m ++ []", "[[1, 2], [3, 4]]"), ("[] ++ m", "[[1, 2], [3, 4]]"),
+            ("shape(m ++ [[5, 6], [7, 8]])", "[4, 2]"), ("[[1, 2]] ++ [[3, 4]]", "[[1, 2], [3, 4]]"),
+            ("m ++ [1, 2, 3]", "error: shapes 2x2 and 1x3 do not match"),
+            ("m ++ [[1, 2, 3]]", "error: shapes 2x2 and 1x3 do not match"),
+            ("m ++ [\"a\", \"b\"]", "error: not supported: Concat on [[1, 2], [3, 4]] and [\"a\", \"b\"]"),
+            ("m ++ 1", "error: not supported: Concat on [[1, 2], [3, 4]] and 1"),
+        ]);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::env::{Env, Func};
+use crate::eval::EvalError;
+use crate::fold::{self, Folder};
+use crate::lexer::Lexer;
+use crate::node::{Closure, Node, Stmt, Value};
+use crate::parser::{Parser, Result};
+
+pub const EXTENSION: &str = "calc";
+
+/// Variables and functions a module defines, under their own names.
+struct Exports {
+    vars: Vec<(String, Value)>,
+    funcs: Vec<(String, Func)>
+}
+
+/// Where modules are looked for, which are loaded, and the files being run,
+/// innermost last.
+#[derive(Clone)]
+pub struct Modules {
+    pub search: Vec<PathBuf>,
+    cache: HashMap<PathBuf, Rc<Exports>>,
+    running: Vec<PathBuf>
+}
+
+/// Prefixes the names a module defines, so bodies keep referring to the
+/// module's own definitions once bound under its namespace.
+struct Qualify<'a> {
+    prefix: &'a str,
+    names: &'a HashSet<&'a str>,
+    /// Parameters, loop variables and lambda parameters, which shadow the
+    /// module's names.
+    bound: Vec<String>
+}
+
+impl Folder for Qualify<'_> {
+    fn fold_var(&mut self, name: String) -> Node {
+        match self.names.contains(name.as_str()) && !self.bound.contains(&name) {
+            true => Node::Var(format!("{}.{}", self.prefix, name)),
+            false => Node::Var(name),
+        }
+    }
+    fn fold_for(&mut self, var: String, seq: Node, body: Node) -> Node {
+        let seq = self.fold_node(seq);
+        self.bound.push(var.clone());
+        let body = self.fold_node(body);
+        self.bound.pop();
+        Node::For(var, Box::new(seq), Box::new(body))
+    }
+    fn fold_lambda(&mut self, params: Vec<String>, body: Node) -> Node {
+        let len = self.bound.len();
+        self.bound.extend(params.iter().cloned());
+        let body = self.fold_node(body);
+        self.bound.truncate(len);
+        Node::Lambda(params, Box::new(body))
+    }
+    fn fold_call(&mut self, name: String, args: Vec<Node>) -> Node {
+        let name = match self.names.contains(name.as_str()) && !self.bound.contains(&name) {
+            true => format!("{}.{}", self.prefix, name),
+            false => name,
+        };
+        fold::walk_call(self, name, args)
+    }
+}
+
+impl Modules {
+    /// Search path from `CALC_PATH`, separated like `PATH`.
+    pub fn new() -> Self {
+        let search = env::var_os("CALC_PATH")
+            .map(|paths| env::split_paths(&paths).collect())
+            .unwrap_or_default();
+        Self {
+            search,
+            cache: HashMap::new(),
+            running: Vec::new()
+        }
+    }
+    /// Resolve imports relative to `path` until `leave`. Fails if `path` is
+    /// already being run.
+    pub fn enter(&mut self, path: &Path) -> std::result::Result<(), EvalError> {
+        let path = path
+            .canonicalize()
+            .map_err(|err| EvalError::Module(format!("{}: {}", path.display(), err)))?;
+        if let Some(start) = self.running.iter().position(|running| *running == path) {
+            let cycle = self.running[start..]
+                .iter()
+                .chain([&path])
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(EvalError::Module(format!("import cycle {}", cycle.join(" -> "))))
+        }
+        self.running.push(path);
+        Ok(())
+    }
+    pub fn leave(&mut self) {
+        self.running.pop();
+    }
+    /// `file` relative to the file being run, or else to the working
+    /// directory, then in each directory of the search path.
+    fn resolve(&self, file: &Path) -> std::result::Result<PathBuf, EvalError> {
+        let base = self.running
+            .last()
+            .and_then(|path| path.parent())
+            .unwrap_or(Path::new("."));
+        [base]
+            .into_iter()
+            .chain(self.search.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+            .ok_or_else(|| EvalError::Module(format!("cannot find {}", file.display())))
+    }
+}
+
+/// Statements of a module, with their line numbers. A statement may span
+/// lines while it is incomplete; `#` starts a comment line.
+fn parse(path: &Path, src: &str) -> std::result::Result<Vec<(usize, Stmt)>, EvalError> {
+    let mut stmts = Vec::new();
+    let mut pending = String::new();
+    let mut start = 0;
+    for (index, line) in src.lines().enumerate() {
+        if pending.is_empty() {
+            if line.trim_start().starts_with('#') {
+                continue
+            }
+            start = index + 1;
+        }
+        pending.push_str(line);
+        pending.push(' ');
+        match Parser::new(Lexer::new(pending.chars()).lenient()).parse_stmt() {
+            Result::None => {}
+            Result::Ok(stmt) => stmts.push((start, stmt)),
+            Result::Incomplete(..) => continue,
+            Result::Err(err) => return Err(EvalError::Module(format!("{}:{}: {}", path.display(), start, err))),
+        }
+        pending.clear();
+    }
+    match Parser::new(Lexer::new(pending.chars()).lenient()).parse_stmt() {
+        Result::Incomplete(err) => Err(EvalError::Module(format!("{}:{}: {}", path.display(), start, err))),
+        _ => Ok(stmts),
+    }
+}
+
+/// Run the module at `path` in an environment of its own, once; later
+/// imports get its exports from the cache.
+fn load(env: &mut Env, path: &Path) -> std::result::Result<Rc<Exports>, EvalError> {
+    let key = path
+        .canonicalize()
+        .map_err(|err| EvalError::Module(format!("{}: {}", path.display(), err)))?;
+    if let Some(exports) = env.modules.cache.get(&key) {
+        return Ok(exports.clone())
+    }
+    let src = fs::read_to_string(path)
+        .map_err(|err| EvalError::Module(format!("{}: {}", path.display(), err)))?;
+    let stmts = parse(path, &src)?;
+
+    let mut module = Env::new();
+    module.settings = env.settings.clone();
+    mem::swap(&mut module.modules, &mut env.modules);
+    let res = module.modules
+        .enter(path)
+        .and_then(|()| {
+            let res = stmts
+                .iter()
+                .try_for_each(|(line, stmt)| match stmt.exec(&mut module) {
+                    Ok(..) => Ok(()),
+                    Err(EvalError::Module(err)) => Err(EvalError::Module(err)),
+                    Err(err) => Err(EvalError::Module(format!("{}:{}: {}", path.display(), line, err))),
+                });
+            module.modules.leave();
+            res
+        });
+    mem::swap(&mut module.modules, &mut env.modules);
+    res?;
+
+    let exports = Rc::new(Exports {
+        vars: module
+            .vars()
+            .into_iter()
+            .map(|(name, val)| (name.clone(), val.clone()))
+            .collect(),
+        funcs: module
+            .funcs()
+            .into_iter()
+            .map(|(name, func)| (name.clone(), func.clone()))
+            .collect()
+    });
+    env.modules.cache.insert(key, exports.clone());
+    Ok(exports)
+}
+
+/// Bind every export as `prefix.name`, and those `unqualified` picks also
+/// under their own name.
+fn bind(env: &mut Env, prefix: &str, exports: &Exports, unqualified: &dyn Fn(&str) -> bool) {
+    let names = exports.vars
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .chain(exports.funcs.iter().map(|(name, _)| name.as_str()))
+        .collect::<HashSet<_>>();
+    for (name, val) in &exports.vars {
+        let val = qualify(val, prefix, &names);
+        env.bind(&format!("{}.{}", prefix, name), val.clone());
+        if unqualified(name) {
+            env.bind(name, val.clone())
+        }
+    }
+    for (name, func) in &exports.funcs {
+        let mut qualify = Qualify {
+            prefix,
+            names: &names,
+            bound: func.params.clone()
+        };
+        let func = Func {
+            params: func.params.clone(),
+            body: qualify.fold_node(func.body.clone())
+        };
+        env.define(&format!("{}.{}", prefix, name), func.clone());
+        if unqualified(name) {
+            env.define(name, func)
+        }
+    }
+}
+
+/// `val` with the bodies of the functions it holds qualified, so a lambda
+/// kept in a module variable also calls into its own module.
+fn qualify(val: &Value, prefix: &str, names: &HashSet<&str>) -> Value {
+    match val {
+        Value::Func(closure) => {
+            let mut folder = Qualify {
+                prefix,
+                names,
+                bound: closure.params
+                    .iter()
+                    .chain(closure.captured.iter().map(|(name, _)| name))
+                    .cloned()
+                    .collect()
+            };
+            Value::Func(Arc::new(Closure {
+                params: closure.params.clone(),
+                body: folder.fold_node(closure.body.clone()),
+                captured: closure.captured
+                    .iter()
+                    .map(|(name, val)| (name.clone(), qualify(val, prefix, names)))
+                    .collect()
+            }))
+        }
+        Value::List(items) => Value::List(items.iter().map(|item| qualify(item, prefix, names)).collect()),
+        val => val.clone(),
+    }
+}
+
+/// `import "file"`: bind the module's names under its file stem.
+pub fn import(env: &mut Env, file: &str) -> std::result::Result<(), EvalError> {
+    let path = env.modules.resolve(Path::new(file))?;
+    let prefix = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| EvalError::Module(format!("invalid module name {}", file)))?
+        .to_string();
+    let exports = load(env, &path)?;
+    bind(env, &prefix, &exports, &|_| false);
+    Ok(())
+}
+
+/// `use a::b`, `use a::b::f` or `use a::b::*`: import `a/b.calc` under `b`,
+/// and bring `f`, or every name, in unqualified.
+pub fn use_module(env: &mut Env, module: &[String], name: Option<&str>) -> std::result::Result<(), EvalError> {
+    let file = module
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension(EXTENSION);
+    let path = env.modules.resolve(&file)?;
+    let prefix = &module[module.len() - 1];
+    let exports = load(env, &path)?;
+    let found = match name {
+        None | Some("*") => true,
+        Some(name) => exports.vars.iter().any(|(var, _)| var == name)
+            || exports.funcs.iter().any(|(func, _)| func == name),
+    };
+    if !found {
+        return Err(EvalError::Module(format!("{} has no {}", module.join("::"), name.unwrap_or_default())))
+    }
+    bind(env, prefix, &exports, &|var| match name {
+        Some("*") => true,
+        Some(name) => var == name,
+        None => false,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::tests::run;
+
+    /// A fresh directory holding `files`, searched for modules by the
+    /// returned environment.
+    fn modules(name: &str, files: &[(&str, &str)]) -> (PathBuf, Env) {
+        let dir = env::temp_dir().join(format!("calc-{}-{}", std::process::id(), name));
+        for (file, src) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+        let mut env = Env::new();
+        env.modules.search = vec![dir.clone()];
+        (dir, env)
+    }
+
+    #[test]
+    fn imports_under_the_file_stem() {
+        let (dir, mut env) = modules("import", &[("finance.calc", "rate = 2\n# doubling\ngrow(x) = x * rate\n")]);
+        assert_eq!(run(&mut env, "import \"finance.calc\""), "");
+        assert_eq!(run(&mut env, "rate = 100"), "100");
+        assert_eq!(run(&mut env, "finance.grow(3)"), "6");
+        assert_eq!(run(&mut env, "finance.rate"), "2");
+        assert_eq!(run(&mut env, "grow(3)"), "error: unknown function grow");
+        assert_eq!(run(&mut env, "import \"missing.calc\""), "error: cannot find missing.calc");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn use_brings_names_in() {
+        let (dir, mut env) = modules("use", &[("geo/shapes.calc", "square(x) = x * x\ncube(x) = x * square(x)\n")]);
+        assert_eq!(run(&mut env, "use geo::shapes::cube"), "");
+        assert_eq!(run(&mut env, "square(x) = 0"), "");
+        assert_eq!(run(&mut env, "cube(2)"), "8");
+        assert_eq!(run(&mut env, "shapes.square(3)"), "9");
+        assert_eq!(run(&mut env, "use geo::shapes::sphere"), "error: geo::shapes has no sphere");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_import_cycles() {
+        let (dir, mut env) = modules("cycle", &[
+            ("a.calc", "x = 1\nimport \"b.calc\"\n"),
+            ("b.calc", "import \"a.calc\"\n"),
+            ("me.calc", "import \"me.calc\"\n"),
+        ]);
+        let path = |file: &str| dir.join(file).canonicalize().unwrap().display().to_string();
+        let cycle = format!("error: import cycle {} -> {} -> {}", path("a.calc"), path("b.calc"), path("a.calc"));
+        assert_eq!(run(&mut env, "import \"a.calc\""), cycle);
+        assert_eq!(run(&mut env, "import \"me.calc\""), format!("error: import cycle {} -> {}", path("me.calc"), path("me.calc")));
+        assert!(env.var("a.x").is_none());
+        // nothing is left running, so later imports still resolve
+        assert!(env.modules.running.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_errors_with_file_and_line() {
+        let (dir, mut env) = modules("errors", &[("bad.calc", "x = 1\n\ny = 1 / 0\n"), ("open.calc", "f(x) = (x +\n")]);
+        let path = |file: &str| dir.join(file).display().to_string();
+        assert_eq!(run(&mut env, "import \"bad.calc\""), format!("error: {}:3: division by zero", path("bad.calc")));
+        assert!(run(&mut env, "import \"open.calc\"").starts_with(&format!("error: {}:1: ", path("open.calc"))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lambdas_in_variables_call_into_their_module() {
+        let (dir, mut env) = modules("lambdas", &[("finance.calc", "two = 2\ndouble = |x| x * two\nfs = [double, |x| two - x]\n")]);
+        run(&mut env, "two = 100");
+        assert_eq!(run(&mut env, "import \"finance.calc\""), "");
+        assert_eq!(run(&mut env, "finance.double(1)"), "2");
+        run(&mut env, "g = finance.fs[1]");
+        assert_eq!(run(&mut env, "g(5)"), "-3");
+        assert_eq!(run(&mut env, "map([1, 2], finance.double)"), "[2, 4]");
+        run(&mut env, "h = |two| finance.double(two)");
+        assert_eq!(run(&mut env, "h(3)"), "6");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,11 @@
+use std::cmp::Ordering;
 use std::fmt;
+use std::sync::Arc;
 
+use crate::complex::Complex;
+use crate::eval::EvalError;
+use crate::matrix::{Matrix, Shape};
+use crate::range::Range;
 use crate::token::Token;
 
 pub enum PrefixOp {
@@ -38,12 +44,48 @@ pub enum BinaryOp {
 
     ShiftLeft,
     ShiftRight,
+
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    LogicAnd,
+    LogicOr,
+
+    Range,
+    RangeIncl,
+
+    Concat,
 }
 
 #[derive(Clone)]
 pub enum Value {
     Str(String),
     Num(i64),
+    Float(f64),
+    Complex(Complex),
+    Range(Range),
+    List(Vec<Value>),
+    Matrix(Matrix),
+    Func(Arc<Closure>),
+}
+
+/// A function value: `|x, y| body`, with the local variables of `body`
+/// as they were when it was made.
+pub struct Closure {
+    pub params: Vec<String>,
+    pub body: Node,
+    pub captured: Vec<(String, Value)>
+}
+
+impl Closure {
+    /// The lambda it was made from, for printing.
+    pub fn lambda(&self) -> Node {
+        Node::Lambda(self.params.clone(), Box::new(self.body.clone()))
+    }
 }
 
 #[derive(Clone)]
@@ -52,6 +94,32 @@ pub enum Node {
     Var(String),
     Unary(UnaryOp, Box<Node>),
     Binary(BinaryOp, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+    /// `if cond then a else b`, or `cond ? a : b`. Only the taken branch
+    /// is evaluated.
+    If(Box<Node>, Box<Node>, Box<Node>),
+    /// `body for x in seq`, with `x` bound to each item of `seq` in turn.
+    /// Only folds such as `sum` take it.
+    For(String, Box<Node>, Box<Node>),
+    /// `[a, b, c]`
+    List(Vec<Node>),
+    /// `xs[i]`, or `xs[a..b]` for a slice.
+    Index(Box<Node>, Box<Node>),
+    /// `|x, y| body`
+    Lambda(Vec<String>, Box<Node>),
+}
+
+#[derive(Clone)]
+pub enum Stmt {
+    Expr(Node),
+    Let(String, Node),
+    Def(String, Vec<String>, Node),
+
+    /// `import "file.calc"`
+    Import(String),
+    /// `use a::b`, `use a::b::f` or `use a::b::*`: the path of a module and
+    /// the name, if any, brought in unqualified.
+    Use(Vec<String>, Option<String>),
 }
 
 impl From<&Token> for Option<PrefixOp> {
@@ -101,6 +169,7 @@ impl From<&Token> for Option<BinaryOp> {
         let res = match i {
             Token::Plus => BinaryOp::Add,
             Token::Dash => BinaryOp::Sub,
+            Token::PlusPlus => BinaryOp::Concat,
 
             Token::Star => BinaryOp::Mul,
             Token::Slash => BinaryOp::Div,
@@ -115,19 +184,104 @@ impl From<&Token> for Option<BinaryOp> {
             Token::LessLess => BinaryOp::ShiftLeft,
             Token::MoreMore => BinaryOp::ShiftRight,
 
+            Token::EqualEqual => BinaryOp::Eq,
+            Token::ExcEqual => BinaryOp::Ne,
+            Token::Less => BinaryOp::Lt,
+            Token::LessEqual => BinaryOp::Le,
+            Token::More => BinaryOp::Gt,
+            Token::MoreEqual => BinaryOp::Ge,
+
+            Token::AndAnd => BinaryOp::LogicAnd,
+            Token::PipePipe => BinaryOp::LogicOr,
+
+            Token::DotDot => BinaryOp::Range,
+            Token::DotDotEqual => BinaryOp::RangeIncl,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl From<&UnaryOp> for Option<PrefixOp> {
+    fn from(i: &UnaryOp) -> Self {
+        let res = match i {
+            UnaryOp::Neg => PrefixOp::Neg,
+            UnaryOp::BitNot => PrefixOp::BitNot,
+            UnaryOp::LogicNot => PrefixOp::LogicNot,
+
             _ => return None,
         };
         Some(res)
     }
 }
 
+impl From<&UnaryOp> for Option<PostfixOp> {
+    fn from(i: &UnaryOp) -> Self {
+        let res = match i {
+            UnaryOp::Fac => PostfixOp::Fac,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl From<&UnaryOp> for Token {
+    fn from(i: &UnaryOp) -> Self {
+        match i {
+            UnaryOp::Neg => Token::Dash,
+            UnaryOp::BitNot => Token::Worm,
+            UnaryOp::LogicNot => Token::Exc,
+
+            UnaryOp::Fac => Token::Exc,
+        }
+    }
+}
+
+impl From<&BinaryOp> for Token {
+    fn from(i: &BinaryOp) -> Self {
+        match i {
+            BinaryOp::Add => Token::Plus,
+            BinaryOp::Sub => Token::Dash,
+
+            BinaryOp::Mul => Token::Star,
+            BinaryOp::Div => Token::Slash,
+            BinaryOp::Mod => Token::Percent,
+
+            BinaryOp::Pow => Token::StarStar,
+
+            BinaryOp::BitAnd => Token::And,
+            BinaryOp::BitOr => Token::Pipe,
+            BinaryOp::BitXor => Token::Hat,
+
+            BinaryOp::ShiftLeft => Token::LessLess,
+            BinaryOp::ShiftRight => Token::MoreMore,
+
+            BinaryOp::Eq => Token::EqualEqual,
+            BinaryOp::Ne => Token::ExcEqual,
+            BinaryOp::Lt => Token::Less,
+            BinaryOp::Le => Token::LessEqual,
+            BinaryOp::Gt => Token::More,
+            BinaryOp::Ge => Token::MoreEqual,
+
+            BinaryOp::LogicAnd => Token::AndAnd,
+            BinaryOp::LogicOr => Token::PipePipe,
+
+            BinaryOp::Range => Token::DotDot,
+            BinaryOp::RangeIncl => Token::DotDotEqual,
+
+            BinaryOp::Concat => Token::PlusPlus,
+        }
+    }
+}
+
 impl From<&Token> for Option<Value> {
     fn from(i: &Token) -> Self {
         let res = match i {
-            Token::Num(n) => {
-                let n = n.clone();
-                Value::Num(n)
-            }
+            Token::Num(n) => Value::Num(*n),
+            Token::Float(f) => Value::Float(*f),
+            Token::Imag(f) => Value::Complex(Complex::new(0.0, *f)),
             Token::Str(s) => {
                 let s = s.clone();
                 Value::Str(s)
@@ -159,6 +313,19 @@ impl PostfixOp {
 impl BinaryOp {
     pub fn binding_power(&self) -> (u8, u8) {
         match self {
+            BinaryOp::LogicOr => (50, 51),
+            BinaryOp::LogicAnd => (52, 53),
+
+            BinaryOp::Range |
+            BinaryOp::RangeIncl => (56, 57),
+
+            BinaryOp::Eq |
+            BinaryOp::Ne |
+            BinaryOp::Lt |
+            BinaryOp::Le |
+            BinaryOp::Gt |
+            BinaryOp::Ge => (60, 61),
+
             BinaryOp::BitOr => (80, 81),
             BinaryOp::BitAnd => (82, 83),
             BinaryOp::BitXor => (84, 85),
@@ -167,7 +334,8 @@ impl BinaryOp {
             BinaryOp::ShiftRight => (110, 111),
 
             BinaryOp::Add | 
-            BinaryOp::Sub => (120, 121),
+            BinaryOp::Sub |
+            BinaryOp::Concat => (120, 121),
 
             BinaryOp::Mul | 
             BinaryOp::Div | 
@@ -209,16 +377,102 @@ impl fmt::Display for BinaryOp {
 
             BinaryOp::ShiftLeft => "ShiftLeft",
             BinaryOp::ShiftRight => "ShiftRight",
+
+            BinaryOp::Eq => "Eq",
+            BinaryOp::Ne => "Ne",
+            BinaryOp::Lt => "Lt",
+            BinaryOp::Le => "Le",
+            BinaryOp::Gt => "Gt",
+            BinaryOp::Ge => "Ge",
+
+            BinaryOp::LogicAnd => "LogicAnd",
+            BinaryOp::LogicOr => "LogicOr",
+
+            BinaryOp::Range => "Range",
+            BinaryOp::RangeIncl => "RangeIncl",
+
+            BinaryOp::Concat => "Concat",
         };
         write!(f, "{}", sym)
     }
 }
 
+impl UnaryOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let res = match name {
+            "Neg" => UnaryOp::Neg,
+            "BitNot" => UnaryOp::BitNot,
+            "LogicNot" => UnaryOp::LogicNot,
+
+            "Fac" => UnaryOp::Fac,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl BinaryOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let res = match name {
+            "Add" => BinaryOp::Add,
+            "Sub" => BinaryOp::Sub,
+
+            "Mul" => BinaryOp::Mul,
+            "Div" => BinaryOp::Div,
+            "Mod" => BinaryOp::Mod,
+
+            "Pow" => BinaryOp::Pow,
+
+            "BitAnd" => BinaryOp::BitAnd,
+            "BitOr" => BinaryOp::BitOr,
+            "BitXor" => BinaryOp::BitXor,
+
+            "ShiftLeft" => BinaryOp::ShiftLeft,
+            "ShiftRight" => BinaryOp::ShiftRight,
+
+            "Eq" => BinaryOp::Eq,
+            "Ne" => BinaryOp::Ne,
+            "Lt" => BinaryOp::Lt,
+            "Le" => BinaryOp::Le,
+            "Gt" => BinaryOp::Gt,
+            "Ge" => BinaryOp::Ge,
+
+            "LogicAnd" => BinaryOp::LogicAnd,
+            "LogicOr" => BinaryOp::LogicOr,
+
+            "Range" => BinaryOp::Range,
+            "RangeIncl" => BinaryOp::RangeIncl,
+
+            "Concat" => BinaryOp::Concat,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Num(n) => return write!(f, "{}", n),
+            Value::Float(x) if x.is_finite() && x.fract() == 0.0 => return write!(f, "{}.0", x),
+            Value::Float(x) => return write!(f, "{}", x),
+            Value::Complex(z) => return write!(f, "{}", z),
             Value::Str(s) => return write!(f, r#""{}""#, s),
+            Value::Range(range) => return write!(f, "{}", range),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}", item)?
+                }
+                write!(f, "]")
+            }
+            Value::Matrix(matrix) => write!(f, "{}", matrix),
+            Value::Func(closure) => write!(f, "{}", closure.lambda().infix()),
         }
     }
 }
@@ -226,103 +480,441 @@ impl fmt::Display for Value {
 impl fmt::Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            // NaN and inf have no literal, so they print as the division
+            // that makes them
+            Node::Val(Value::Float(x)) if x.is_nan() => write!(f, "(Div 0.0 0.0)"),
+            Node::Val(Value::Float(x)) if x.is_infinite() => write!(f, "(Div {}1.0 0.0)", if *x < 0.0 { "-" } else { "" }),
+            // values with no atom print as the form that builds them, which
+            // reads back
+            Node::Val(Value::Range(range)) if range.step != 1 => {
+                write!(f, "(Call step {} {})", Range { step: 1, ..range.clone() }, range.step)
+            }
+            Node::Val(Value::List(items)) => {
+                write!(f, "(List")?;
+                for item in items {
+                    write!(f, " {}", Node::Val(item.clone()))?;
+                }
+                write!(f, ")")
+            }
+            Node::Val(Value::Matrix(matrix)) => write!(f, "{}", Node::Val(Value::List(matrix.rows().collect()))),
+            Node::Val(Value::Func(closure)) => write!(f, "{}", closure.lambda()),
+            // `infi` and `NaNi` would read as names
+            Node::Val(Value::Complex(z)) if z.re == 0.0 && !z.im.is_finite() => {
+                let sign = if z.im.is_sign_negative() { "-" } else { "+" };
+                write!(f, "0{}{}i", sign, z.im.abs())
+            }
             Node::Val(val) => write!(f, "{}", val),
             Node::Var(name) => write!(f, "{}", name),
             Node::Unary(op, node) => write!(f, "({} {})", op, node),
             Node::Binary(op, left, right) => write!(f, "({} {} {})", op, left, right),
+            Node::Call(name, args) => {
+                write!(f, "(Call {}", name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Node::If(cond, then, other) => write!(f, "(If {} {} {})", cond, then, other),
+            Node::For(var, seq, body) => write!(f, "(For {} {} {})", var, seq, body),
+            Node::List(items) => {
+                write!(f, "(List")?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, ")")
+            }
+            Node::Index(node, index) => write!(f, "(Index {} {})", node, index),
+            Node::Lambda(params, body) => {
+                write!(f, "(Lambda")?;
+                for param in params {
+                    write!(f, " {}", param)?;
+                }
+                write!(f, " {})", body)
+            }
         }
     }
 }
 
-impl BinaryOp {}
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Expr(node) => write!(f, "{}", node),
+            Stmt::Let(name, node) => write!(f, "(Let {} {})", name, node),
+            Stmt::Def(name, params, node) => write!(f, "(Def {} ({}) {})", name, params.join(" "), node),
+
+            Stmt::Import(path) => write!(f, r#"(Import "{}")"#, path),
+            Stmt::Use(path, None) => write!(f, "(Use {})", path.join("::")),
+            Stmt::Use(path, Some(name)) => write!(f, "(Use {} {})", path.join("::"), name),
+        }
+    }
+}
 
-impl Node {
-    pub fn eval(self) -> Node {
+impl Stmt {
+    /// The expression a statement evaluates: itself, an assigned value or
+    /// a function body. Imports have none.
+    pub fn node(&self) -> Option<&Node> {
+        match self {
+            Stmt::Expr(node) => Some(node),
+            Stmt::Let(_, node) => Some(node),
+            Stmt::Def(_, _, node) => Some(node),
+            Stmt::Import(..) | Stmt::Use(..) => None,
+        }
+    }
+}
+
+impl UnaryOp {
+    pub fn apply(&self, val: Value) -> Result<Value, EvalError> {
+        let n = match val {
+            Value::List(items) => {
+                return items
+                    .into_iter()
+                    .map(|item| self.apply(item))
+                    .collect::<Result<_, _>>()
+                    .map(Value::List)
+            }
+            Value::Matrix(matrix) => return matrix.map(&|item| self.apply(item)).map(Value::Matrix),
+            Value::Num(n) => n,
+            Value::Float(x) if let UnaryOp::Neg = self => return Ok(Value::Float(-x)),
+            Value::Complex(z) if let UnaryOp::Neg = self => return Ok(Value::Complex(z.neg())),
+            val => return Err(EvalError::Unsupported(format!("{} on {}", self, val))),
+        };
+        let res = match self {
+            UnaryOp::Neg => n.checked_neg(),
+            UnaryOp::BitNot => Some(!n),
+            UnaryOp::LogicNot => Some((n == 0) as i64),
+
+            UnaryOp::Fac if n < 0 => return Err(EvalError::Unsupported(format!("factorial of {}", n))),
+            UnaryOp::Fac => (1..=n).try_fold(1i64, i64::checked_mul),
+        };
+        res.map(Value::Num).ok_or(EvalError::Overflow)
+    }
+}
+
+impl Value {
+    /// A list, or a matrix when the items are rows of numbers of the same
+    /// length, as `[[1, 2], [3, 4]]` is.
+    pub fn list(items: Vec<Value>) -> Value {
+        match Matrix::from_rows(&items) {
+            Some(matrix) => Value::Matrix(matrix),
+            None => Value::List(items),
+        }
+    }
+    /// Truth of a condition: non-zero numbers are true.
+    pub fn truthy(&self) -> Result<bool, EvalError> {
         match self {
-            Node::Binary(op, left, right) => {
-                let left_res = left.eval();
-                let right_res = right.eval();
-                match op {
-                    BinaryOp::Add => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) =>  Node::Val(Value::Num(left_num + right_num)),
-                        _ => panic!("not supported")
-                    },
-                    BinaryOp::Sub => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num - right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::Mul => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num * right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::Div => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) =>  Node::Val(Value::Num(left_num / right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::Mod => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num % right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::Pow => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num.pow(right_num as u32))),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::BitAnd => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num & right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::BitOr => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num | right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::BitXor => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num ^ right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::ShiftLeft => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num << right_num)),
-                        _ => panic!("not supported"),
-                    },
-                    BinaryOp::ShiftRight => match (left_res, right_res) {
-                        (
-                            Node::Val(Value::Num(left_num)), 
-                            Node::Val(Value::Num(right_num))
-                        ) => Node::Val(Value::Num(left_num >> right_num)),
-                        _ => panic!("not supported"),
-                    },
+            Value::Num(n) => Ok(*n != 0),
+            Value::Float(x) => Ok(*x != 0.0),
+            val => Err(EvalError::Unsupported(format!("condition {}", val))),
+        }
+    }
+    /// Items of a sequence, for loops and folds.
+    pub fn items(&self) -> Option<Box<dyn Iterator<Item = Value> + '_>> {
+        match self {
+            Value::Range(range) => Some(Box::new(range.iter().map(Value::Num))),
+            Value::List(items) => Some(Box::new(items.iter().cloned())),
+            Value::Matrix(matrix) => Some(Box::new(matrix.rows())),
+            _ => None,
+        }
+    }
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Value::Range(range) => Some(range.len()),
+            Value::List(items) => Some(items.len()),
+            Value::Matrix(matrix) => Some(matrix.rows),
+            Value::Str(s) => Some(s.chars().count()),
+            _ => None,
+        }
+    }
+    /// `xs[i]`, counting from the end for negative `i`, or the slice
+    /// `xs[a..b]`. Strings index by character, matrices by row.
+    pub fn index(&self, index: &Value) -> Result<Value, EvalError> {
+        let len = match self {
+            Value::List(..) | Value::Str(..) | Value::Matrix(..) => self.len().unwrap(),
+            val => return Err(EvalError::Unsupported(format!("indexing {}", val))),
+        };
+        let at = |i: i64| match i < 0 {
+            true if i >= -(len as i64) => Ok((len as i64 + i) as usize),
+            false if i < len as i64 => Ok(i as usize),
+            _ => Err(EvalError::Index { index: i, len }),
+        };
+        let (at, slice) = match index {
+            Value::Num(i) => (vec![at(*i)?], false),
+            Value::Range(range) => (range.iter().map(at).collect::<Result<Vec<_>, _>>()?, true),
+            index => return Err(EvalError::Unsupported(format!("index {}", index))),
+        };
+        match self {
+            Value::List(items) if slice => Ok(Value::List(at.iter().map(|&i| items[i].clone()).collect())),
+            Value::List(items) => Ok(items[at[0]].clone()),
+            Value::Matrix(matrix) if slice => Ok(Value::list(at.iter().map(|&i| matrix.row(i)).collect())),
+            Value::Matrix(matrix) => Ok(matrix.row(at[0])),
+            Value::Str(s) => {
+                let chars = s.chars().collect::<Vec<_>>();
+                Ok(Value::Str(at.iter().map(|&i| chars[i]).collect()))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge)
+    }
+    /// Whether `op` goes item by item over `left` and `right`: every
+    /// operator but `++` does when either is a list or a matrix.
+    pub fn broadcasts(&self, left: &Value, right: &Value) -> bool {
+        !matches!(self, BinaryOp::Concat)
+            && [left, right].iter().any(|val| matches!(val, Value::List(..) | Value::Matrix(..)))
+    }
+    /// `[1, 2] * 2` is `[2, 4]` and `[1, 2] + [3, 4]` is `[4, 6]`, with `f`
+    /// applied to each pair of items. Lists must be the same length, and
+    /// matrices the same shape, except for `*` with a matrix, which is the
+    /// matrix product.
+    pub fn broadcast(&self, left: Value, right: Value, f: &dyn Fn(Value, Value) -> Result<Value, EvalError>) -> Result<Value, EvalError> {
+        if matches!(left, Value::Matrix(..)) || matches!(right, Value::Matrix(..)) {
+            return self.broadcast_matrix(left, right, f)
+        }
+        let items = match (left, right) {
+            (Value::List(left), Value::List(right)) => {
+                if left.len() != right.len() {
+                    return Err(EvalError::Lengths(left.len(), right.len()))
                 }
+                left.into_iter()
+                    .zip(right)
+                    .map(|(left, right)| f(left, right))
+                    .collect::<Result<_, _>>()?
+            }
+            (Value::List(left), right) => left
+                .into_iter()
+                .map(|left| f(left, right.clone()))
+                .collect::<Result<_, _>>()?,
+            (left, Value::List(right)) => right
+                .into_iter()
+                .map(|right| f(left.clone(), right))
+                .collect::<Result<_, _>>()?,
+            (left, right) => return f(left, right),
+        };
+        Ok(Value::List(items))
+    }
+    fn broadcast_matrix(&self, left: Value, right: Value, f: &dyn Fn(Value, Value) -> Result<Value, EvalError>) -> Result<Value, EvalError> {
+        let mul = matches!(self, BinaryOp::Mul);
+        match (left, right) {
+            (Value::Matrix(left), Value::Matrix(right)) if mul => left.mul(&right, f).map(Value::Matrix),
+            (Value::Matrix(left), Value::Matrix(right)) => left.zip(right, f).map(Value::Matrix),
+            // a list of rows is a matrix, a list of numbers a vector
+            (Value::List(left), Value::Matrix(right)) => match Matrix::from_rows(&left) {
+                Some(left) => self.broadcast_matrix(Value::Matrix(left), Value::Matrix(right), f),
+                None if mul => Matrix::column(left)
+                    .transpose()
+                    .mul(&right, f)
+                    .map(|row| Value::List(row.items)),
+                None => Err(EvalError::Shape(Shape(left.len(), 1), right.shape())),
+            },
+            (Value::Matrix(left), Value::List(right)) => match Matrix::from_rows(&right) {
+                Some(right) => self.broadcast_matrix(Value::Matrix(left), Value::Matrix(right), f),
+                None if mul => left
+                    .mul(&Matrix::column(right), f)
+                    .map(|column| Value::List(column.items)),
+                None => Err(EvalError::Shape(left.shape(), Shape(right.len(), 1))),
             },
-            node => node,
+            (Value::Matrix(left), right) => left.map(&|left| f(left, right.clone())).map(Value::Matrix),
+            (left, Value::Matrix(right)) => right.map(&|right| f(left.clone(), right)).map(Value::Matrix),
+            (left, right) => f(left, right),
+        }
+    }
+    /// Comparisons and logic give 1 for true and 0 for false.
+    pub fn apply(&self, left: Value, right: Value) -> Result<Value, EvalError> {
+        if self.broadcasts(&left, &right) {
+            return self.broadcast(left, right, &|left, right| self.apply(left, right))
+        }
+        if let BinaryOp::Concat = self {
+            return match (left, right) {
+                (Value::List(mut left), Value::List(right)) => {
+                    left.extend(right);
+                    Ok(Value::List(left))
+                }
+                (Value::Str(left), Value::Str(right)) => Ok(Value::Str(left + &right)),
+                (Value::Matrix(matrix), Value::List(items)) |
+                (Value::List(items), Value::Matrix(matrix)) if items.is_empty() => Ok(Value::Matrix(matrix)),
+                (left @ (Value::Matrix(..) | Value::List(..)), right @ (Value::Matrix(..) | Value::List(..))) => {
+                    match (Matrix::from_value(&left), Matrix::from_value(&right)) {
+                        (Some(left), Some(right)) => left.concat(right).map(Value::Matrix),
+                        _ => Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+                    }
+                }
+                (left, right) => Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+            }
+        }
+        if let BinaryOp::LogicAnd | BinaryOp::LogicOr = self {
+            let (left, right) = (left.truthy()?, right.truthy()?);
+            let res = match self {
+                BinaryOp::LogicAnd => left && right,
+                _ => left || right,
+            };
+            return Ok(Value::Num(res as i64))
+        }
+        if let BinaryOp::Range | BinaryOp::RangeIncl = self {
+            return match (left, right) {
+                (Value::Num(start), Value::Num(end)) => {
+                    Ok(Value::Range(Range::new(start, end, matches!(self, BinaryOp::RangeIncl))))
+                }
+                (left, right) => Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+            }
         }
+        if matches!(left, Value::Complex(..)) || matches!(right, Value::Complex(..)) {
+            return self.apply_complex(left, right)
+        }
+        let (left_num, right_num) = match (left, right) {
+            (Value::Str(left), Value::Str(right)) if self.is_comparison() => {
+                return Ok(Value::Num(self.compare(left.cmp(&right)) as i64))
+            }
+            (Value::Num(left_num), Value::Num(right_num)) => (left_num, right_num),
+            (Value::Float(left_num), Value::Float(right_num)) => return self.apply_float(left_num, right_num),
+            (Value::Num(left_num), Value::Float(right_num)) => return self.apply_float(left_num as f64, right_num),
+            (Value::Float(left_num), Value::Num(right_num)) => return self.apply_float(left_num, right_num as f64),
+            (left, right) => return Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+        };
+        let res = match self {
+            BinaryOp::Add => left_num.checked_add(right_num),
+            BinaryOp::Sub => left_num.checked_sub(right_num),
+
+            BinaryOp::Mul => left_num.checked_mul(right_num),
+            BinaryOp::Div |
+            BinaryOp::Mod if right_num == 0 => return Err(EvalError::DivByZero),
+            BinaryOp::Div => left_num.checked_div(right_num),
+            BinaryOp::Mod => left_num.checked_rem(right_num),
+
+            BinaryOp::Pow if right_num < 0 => {
+                return Err(EvalError::Unsupported(format!("{} with negative exponent {}", self, right_num)))
+            }
+            BinaryOp::Pow => u32::try_from(right_num)
+                .ok()
+                .and_then(|exp| left_num.checked_pow(exp)),
+
+            BinaryOp::BitAnd => Some(left_num & right_num),
+            BinaryOp::BitOr => Some(left_num | right_num),
+            BinaryOp::BitXor => Some(left_num ^ right_num),
+
+            BinaryOp::ShiftLeft => u32::try_from(right_num)
+                .ok()
+                .and_then(|sh| left_num.checked_shl(sh)),
+            BinaryOp::ShiftRight => u32::try_from(right_num)
+                .ok()
+                .and_then(|sh| left_num.checked_shr(sh)),
+
+            op => Some(op.compare(left_num.cmp(&right_num)) as i64),
+        };
+        res.map(Value::Num).ok_or(EvalError::Overflow)
+    }
+    /// Arithmetic and equality with a complex operand. Results with no
+    /// imaginary part are floats.
+    fn apply_complex(&self, left: Value, right: Value) -> Result<Value, EvalError> {
+        let (z, w) = match (Complex::from_value(&left), Complex::from_value(&right)) {
+            (Some(z), Some(w)) => (z, w),
+            _ => return Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+        };
+        let res = match self {
+            BinaryOp::Add => z.add(w),
+            BinaryOp::Sub => z.sub(w),
+
+            BinaryOp::Mul => z.mul(w),
+            BinaryOp::Div => z.div(w)?,
+
+            BinaryOp::Pow => z.pow(&right)?,
+
+            BinaryOp::Eq => return Ok(Value::Num((z == w) as i64)),
+            BinaryOp::Ne => return Ok(Value::Num((z != w) as i64)),
+
+            _ => return Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right))),
+        };
+        Ok(res.value())
+    }
+    fn compare(&self, ord: Ordering) -> bool {
+        match self {
+            BinaryOp::Eq => ord.is_eq(),
+            BinaryOp::Ne => ord.is_ne(),
+            BinaryOp::Lt => ord.is_lt(),
+            BinaryOp::Le => ord.is_le(),
+            BinaryOp::Gt => ord.is_gt(),
+            BinaryOp::Ge => ord.is_ge(),
+            _ => unreachable!(),
+        }
+    }
+    fn apply_float(&self, left_num: f64, right_num: f64) -> Result<Value, EvalError> {
+        if self.is_comparison() {
+            // NaN compares unequal to everything
+            let res = match left_num.partial_cmp(&right_num) {
+                Some(ord) => self.compare(ord),
+                None => matches!(self, BinaryOp::Ne),
+            };
+            return Ok(Value::Num(res as i64))
+        }
+        let res = match self {
+            BinaryOp::Add => left_num + right_num,
+            BinaryOp::Sub => left_num - right_num,
+
+            BinaryOp::Mul => left_num * right_num,
+            BinaryOp::Div => left_num / right_num,
+            BinaryOp::Mod => left_num % right_num,
+
+            BinaryOp::Pow => left_num.powf(right_num),
+
+            _ => {
+                let (left, right) = (Value::Float(left_num), Value::Float(right_num));
+                return Err(EvalError::Unsupported(format!("{} on {} and {}", self, left, right)))
+            }
+        };
+        Ok(Value::Float(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::Env;
+    use crate::eval::tests::run;
+
+    fn check(env: &mut Env, cases: &[(&str, &str)]) {
+        for (src, res) in cases {
+            assert_eq!(run(env, src), *res, "{}", src);
+        }
+    }
+
+    #[test]
+    fn indexes_from_either_end() {
+        let mut env = Env::new();
+        run(&mut env, "xs = [1, 2, 3, 4, 5]");
+        check(&mut env, &[
+            ("xs[0]", "1"), ("xs[-1]", "5"), ("xs[-5]", "1"), ("\"hello\"[1]", "\"e\""),
+            ("xs[5]", "error: index 5 out of range for length 5"),
+            ("xs[-6]", "error: index -6 out of range for length 5"),
+            ("xs[-9223372036854775807 - 1]", "error: index -9223372036854775808 out of range for length 5"),
+            ("[][0]", "error: index 0 out of range for length 0"),
+            ("xs[1.5]", "error: not supported: index 1.5"),
+            ("(1..5)[1]", "error: not supported: indexing 1..5"),
+        ]);
+    }
+
+    #[test]
+    fn slices_by_ranges_of_indices() {
+        let mut env = Env::new();
+        run(&mut env, "xs = [1, 2, 3, 4, 5]");
+        check(&mut env, &[
+            ("xs[1..3]", "[2, 3]"), ("xs[1..=3]", "[2, 3, 4]"), ("xs[3..1]", "[]"), ("xs[0..5]", "[1, 2, 3, 4, 5]"),
+            ("xs[step(0..5, 2)]", "[1, 3, 5]"), ("xs[step(4..=0, -1)]", "[5, 4, 3, 2, 1]"),
+            ("xs[-2..0]", "[4, 5]"), ("\"hello\"[1..3]", "\"el\""),
+            ("xs[0..10]", "error: index 5 out of range for length 5"),
+        ]);
+    }
+
+    #[test]
+    fn broadcasts_over_lists() {
+        let mut env = Env::new();
+        check(&mut env, &[
+            ("[1, 2] + [10, 20]", "[11, 22]"), ("[1, 2] * 2", "[2, 4]"), ("2 - [1, 2]", "[1, 0]"), ("-[1, 2]", "[-1, -2]"),
+            ("[[1], [2, 3]] * 2", "[[2], [4, 6]]"), ("[1, 2] < [2, 2]", "[1, 0]"),
+            ("[1, 2] ++ [3]", "[1, 2, 3]"), ("\"ab\" ++ \"cd\"", "\"abcd\""), ("[] ++ []", "[]"),
+            ("[1, 2] + [1]", "error: lengths 2 and 1 differ"),
+            ("[1, 2] ++ 3", "error: not supported: Concat on [1, 2] and 3"),
+        ]);
     }
 }
@@ -1,18 +1,29 @@
 use std::iter::Peekable;
 
 use crate::token::Token;
-use crate::node::{Node, Value, PrefixOp, PostfixOp, UnaryOp, BinaryOp};
+use crate::node::{Node, Stmt, Value, PrefixOp, PostfixOp, UnaryOp, BinaryOp};
 
 use crate::lexer::{Lexer, CharIterator};
 
+/// Binding power of `cond ? a : b`, below every binary operator.
+const TERNARY: u8 = 40;
+/// Binding power of `xs[i]`, above every operator.
+pub const INDEX: u8 = 140;
+/// Binding power of `body for x in seq`, below everything else, so that
+/// `if c then a else b for x in seq` loops over the whole `if`.
+const FOR: u8 = 0;
+
 pub struct Parser<T: CharIterator> {
     feed: Peekable<Lexer<T>>
 }
 
-pub enum Result {
+pub enum Result<T = Node> {
     None,
-    Ok(Node),
-    Err(String)
+    Ok(T),
+    Err(String),
+    /// Input ended where more was expected, e.g. after `(` or a trailing
+    /// operator. More lines may complete it.
+    Incomplete(String)
 }
 
 impl <T: CharIterator> Parser<T> {
@@ -23,8 +34,8 @@ impl <T: CharIterator> Parser<T> {
     }
     pub fn parse(&mut self) -> Result {
         let node = self.parse_inner(0);
-        if let Result::Err(err) = node {
-            return Result::Err(err)
+        if let Result::Err(..) | Result::Incomplete(..) = node {
+            return node
         }
         if let Some(tok) = self.feed.next() {
             let err = format!("parser expect end of input, found {}", tok);
@@ -32,18 +43,158 @@ impl <T: CharIterator> Parser<T> {
         }
         node
     }
+    /// Parse an expression, an assignment `x = expr`, a function
+    /// definition `f(x, y) = expr`, an `import "file"` or a `use a::b`.
+    pub fn parse_stmt(&mut self) -> Result<Stmt> {
+        let node = match self.parse_inner(0) {
+            Result::None => return Result::None,
+            Result::Err(err) => return Result::Err(err),
+            Result::Incomplete(err) => return Result::Incomplete(err),
+            Result::Ok(node) => node
+        };
+        let stmt = match (&node, self.feed.peek()) {
+            (Node::Var(name), Some(Token::Str(..))) if name == "import" => match self.feed.next() {
+                Some(Token::Str(path)) => Stmt::Import(path),
+                _ => unreachable!()
+            }
+            (Node::Var(name), Some(Token::Name(..))) if name == "use" => match self.parse_use() {
+                Result::Ok(stmt) => stmt,
+                res => return res
+            }
+            _ => match self.parse_assign(node) {
+                Result::Ok(stmt) => stmt,
+                res => return res
+            }
+        };
+        if let Some(tok) = self.feed.next() {
+            let err = format!("parser expect end of input, found {}", tok);
+            return Result::Err(err)
+        }
+        Result::Ok(stmt)
+    }
+    fn parse_use(&mut self) -> Result<Stmt> {
+        let mut path = Vec::new();
+        let mut all = false;
+        loop {
+            match self.feed.next() {
+                Some(Token::Name(name)) => path.push(name),
+                Some(Token::Star) if !path.is_empty() => {
+                    all = true;
+                    break
+                }
+                Some(tok) => {
+                    let err = format!("use expect name, found {}", tok);
+                    return Result::Err(err)
+                }
+                None => {
+                    let err = format!("use expect name");
+                    return Result::Incomplete(err)
+                }
+            }
+            match self.feed.peek() {
+                Some(Token::ColonColon) => self.feed.next(),
+                _ => break
+            };
+        }
+        let stmt = match (all, path.len()) {
+            (true, _) => Stmt::Use(path, Some(format!("*"))),
+            (false, 1) => Stmt::Use(path, None),
+            (false, _) => {
+                let name = path.pop();
+                Stmt::Use(path, name)
+            }
+        };
+        Result::Ok(stmt)
+    }
+    fn parse_assign(&mut self, node: Node) -> Result<Stmt> {
+        let stmt = match self.feed.peek() {
+            Some(Token::Equal) => {
+                self.feed.next();
+                let value = match self.parse_inner(0) {
+                    Result::None => {
+                        let err = format!("assignment expect expression");
+                        return Result::Incomplete(err)
+                    }
+                    Result::Err(err) => return Result::Err(err),
+                    Result::Incomplete(err) => return Result::Incomplete(err),
+                    Result::Ok(value) => value
+                };
+                match node {
+                    Node::Var(name) => Stmt::Let(name, value),
+                    Node::Call(name, args) => {
+                        let params = args
+                            .into_iter()
+                            .map(|arg| match arg {
+                                Node::Var(param) => Some(param),
+                                _ => None
+                            })
+                            .collect::<Option<Vec<_>>>();
+                        match params {
+                            Some(params) => Stmt::Def(name, params, value),
+                            None => {
+                                let err = format!("parameters of {} must be names", name);
+                                return Result::Err(err)
+                            }
+                        }
+                    }
+                    node => {
+                        let err = format!("cannot assign to {}", node.infix());
+                        return Result::Err(err)
+                    }
+                }
+            }
+            _ => Stmt::Expr(node)
+        };
+        Result::Ok(stmt)
+    }
     fn parse_inner(&mut self, power: u8) -> Result {
         let mut node = match self.feed.next() {
             None => return Result::None,
             Some(tok) => match tok {
-                Token::Name(name) => Node::Var(name),
+                Token::Name(name) if name == "if" => match self.parse_if() {
+                    Result::Ok(node) => node,
+                    res => return res
+                }
+                Token::Name(mut name) => {
+                    // qualified names such as `finance.npv`
+                    while let Some(Token::Dot) = self.feed.peek() {
+                        self.feed.next();
+                        match self.feed.next() {
+                            Some(Token::Name(part)) => {
+                                name.push('.');
+                                name.push_str(&part)
+                            }
+                            Some(tok) => {
+                                let err = format!("expect name after ., found {}", tok);
+                                return Result::Err(err)
+                            }
+                            None => {
+                                let err = format!("expect name after .");
+                                return Result::Incomplete(err)
+                            }
+                        }
+                    }
+                    match self.feed.peek() {
+                        Some(Token::OpenParen) => {
+                            self.feed.next();
+                            match self.parse_args() {
+                                Result::Ok(args) => Node::Call(name, args),
+                                Result::Err(err) => return Result::Err(err),
+                                Result::Incomplete(err) => return Result::Incomplete(err),
+                                Result::None => unreachable!()
+                            }
+                        }
+                        _ => Node::Var(name)
+                    }
+                }
                 tok if let Some(val) = Option::<Value>::from(&tok) => Node::Val(val),
                 Token::OpenParen => match self.parse_inner(0) {
                     Result::None => {
                         let err = format!("open parentheses expect expression");
-                        return Result::Err(err)
+                        return Result::Incomplete(err)
                     }
                     Result::Err(err) => return Result::Err(err),
+                    Result::Incomplete(err) => return Result::Incomplete(err),
                     Result::Ok(node) => match self.feed.next() {
                         Some(Token::CloseParen) => node,
                         Some(tok) => {
@@ -52,17 +203,37 @@ impl <T: CharIterator> Parser<T> {
                         }
                         None => {
                             let err = format!("expect close parentheses");
-                            return Result::Err(err)
+                            return Result::Incomplete(err)
                         }
                     }
                 }
+                Token::OpenBracket => match self.parse_list() {
+                    Result::Ok(items) => Node::List(items),
+                    Result::Err(err) => return Result::Err(err),
+                    Result::Incomplete(err) => return Result::Incomplete(err),
+                    Result::None => unreachable!()
+                }
+                Token::Pipe => match self.parse_params() {
+                    Result::Ok(params) => match self.parse_lambda(params) {
+                        Result::Ok(node) => node,
+                        res => return res
+                    }
+                    Result::Err(err) => return Result::Err(err),
+                    Result::Incomplete(err) => return Result::Incomplete(err),
+                    Result::None => unreachable!()
+                }
+                Token::PipePipe => match self.parse_lambda(Vec::new()) {
+                    Result::Ok(node) => node,
+                    res => return res
+                }
                 tok if let Some(op) = Option::<PrefixOp>::from(&tok) => {
                     let right_power = op.binding_power();
                     match self.parse_inner(right_power) {
                         Result::Err(err) => return Result::Err(err),
+                        Result::Incomplete(err) => return Result::Incomplete(err),
                         Result::None => {
                             let err = format!("prefix operator expect expression");
-                            return Result::Err(err)
+                            return Result::Incomplete(err)
                         }
                         Result::Ok(node) => Node::Unary(
                             UnaryOp::from(&op),
@@ -70,6 +241,7 @@ impl <T: CharIterator> Parser<T> {
                         )
                     }
                 }
+                tok if let Some(err) = tok.error() => return Result::Err(err),
                 tok => {
                     let err = format!("expect prefix token, found {}", tok);
                     return Result::Err(err)
@@ -81,7 +253,63 @@ impl <T: CharIterator> Parser<T> {
                 None => break,
                 Some(tok) => tok
             };
-            if let Some(op) = Option::<PostfixOp>::from(tok) {
+            if let Some(err) = tok.error() {
+                return Result::Err(err)
+            }
+            if matches!(tok, Token::Name(name) if name == "for") && power == FOR {
+                self.feed.next();
+                node = match self.parse_for(node) {
+                    Result::Ok(node) => node,
+                    res => return res
+                };
+                if matches!(self.feed.peek(), Some(Token::Name(name)) if name == "for") {
+                    let err = format!("for expect one loop, put the inner one in parentheses");
+                    return Result::Err(err)
+                }
+            }
+            else if let (Token::OpenBracket, true) = (tok, INDEX >= power) {
+                self.feed.next();
+                let index = match self.operand(0, "open bracket") {
+                    Result::Ok(index) => index,
+                    res => return res
+                };
+                match self.feed.next() {
+                    Some(Token::CloseBracket) => {}
+                    Some(tok) => {
+                        let err = format!("expect close bracket, found {}", tok);
+                        return Result::Err(err)
+                    }
+                    None => {
+                        let err = format!("expect close bracket");
+                        return Result::Incomplete(err)
+                    }
+                }
+                node = Node::Index(Box::new(node), Box::new(index))
+            }
+            else if let (Token::Question, true) = (tok, TERNARY >= power) {
+                self.feed.next();
+                let then = match self.operand(FOR + 1, "?") {
+                    Result::Ok(node) => node,
+                    res => return res
+                };
+                match self.feed.next() {
+                    Some(Token::Colon) => {}
+                    Some(tok) => {
+                        let err = format!("expect :, found {}", tok);
+                        return Result::Err(err)
+                    }
+                    None => {
+                        let err = format!("expect :");
+                        return Result::Incomplete(err)
+                    }
+                }
+                let other = match self.operand(TERNARY, ":") {
+                    Result::Ok(node) => node,
+                    res => return res
+                };
+                node = Node::If(Box::new(node), Box::new(then), Box::new(other))
+            }
+            else if let Some(op) = Option::<PostfixOp>::from(tok) {
                 let left_power = op.binding_power();
                 if left_power < power { 
                     break 
@@ -100,9 +328,10 @@ impl <T: CharIterator> Parser<T> {
                 self.feed.next();
                 match self.parse_inner(right_power) {
                     Result::Err(err) => return Result::Err(err),
+                    Result::Incomplete(err) => return Result::Incomplete(err),
                     Result::None => {
                         let err = format!("infix operator expect expression");
-                        return Result::Err(err)
+                        return Result::Incomplete(err)
                     }
                     Result::Ok(right) => node = Node::Binary(
                         op,
@@ -115,4 +344,241 @@ impl <T: CharIterator> Parser<T> {
         }
         Result::Ok(node)
     }
+    /// An expression that must be there, after `what`.
+    fn operand(&mut self, power: u8, what: &str) -> Result {
+        match self.parse_inner(power) {
+            Result::None => {
+                let err = format!("{} expect expression", what);
+                Result::Incomplete(err)
+            }
+            res => res
+        }
+    }
+    /// The keyword `word`, or the result to fail with.
+    fn keyword(&mut self, word: &str) -> Option<Result> {
+        match self.feed.next() {
+            Some(Token::Name(name)) if name == word => None,
+            Some(tok) => {
+                let err = format!("expect {}, found {}", word, tok);
+                Some(Result::Err(err))
+            }
+            None => {
+                let err = format!("expect {}", word);
+                Some(Result::Incomplete(err))
+            }
+        }
+    }
+    /// `if cond then a else b`, after the `if`.
+    fn parse_if(&mut self) -> Result {
+        let cond = match self.operand(FOR + 1, "if") {
+            Result::Ok(node) => node,
+            res => return res
+        };
+        if let Some(res) = self.keyword("then") {
+            return res
+        }
+        let then = match self.operand(FOR + 1, "then") {
+            Result::Ok(node) => node,
+            res => return res
+        };
+        if let Some(res) = self.keyword("else") {
+            return res
+        }
+        let other = match self.operand(FOR + 1, "else") {
+            Result::Ok(node) => node,
+            res => return res
+        };
+        Result::Ok(Node::If(Box::new(cond), Box::new(then), Box::new(other)))
+    }
+    /// `body for x in seq`, after the `for`.
+    fn parse_for(&mut self, body: Node) -> Result {
+        let var = match self.feed.next() {
+            Some(Token::Name(name)) => name,
+            Some(tok) => {
+                let err = format!("for expect name, found {}", tok);
+                return Result::Err(err)
+            }
+            None => {
+                let err = format!("for expect name");
+                return Result::Incomplete(err)
+            }
+        };
+        if let Some(res) = self.keyword("in") {
+            return res
+        }
+        let seq = match self.operand(FOR + 1, "in") {
+            Result::Ok(node) => node,
+            res => return res
+        };
+        Result::Ok(Node::For(var, Box::new(seq), Box::new(body)))
+    }
+    /// Parameters of `|x, y| body`, after the first `|`.
+    fn parse_params(&mut self) -> Result<Vec<String>> {
+        let mut params = Vec::new();
+        loop {
+            match self.feed.next() {
+                Some(Token::Name(name)) => params.push(name),
+                Some(tok) => {
+                    let err = format!("lambda expect name, found {}", tok);
+                    return Result::Err(err)
+                }
+                None => {
+                    let err = format!("lambda expect name");
+                    return Result::Incomplete(err)
+                }
+            }
+            match self.feed.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::Pipe) => return Result::Ok(params),
+                Some(tok) => {
+                    let err = format!("expect comma or |, found {}", tok);
+                    return Result::Err(err)
+                }
+                None => {
+                    let err = format!("expect |");
+                    return Result::Incomplete(err)
+                }
+            }
+        }
+    }
+    /// The body of a lambda, which reaches as far as an `if` branch does.
+    fn parse_lambda(&mut self, params: Vec<String>) -> Result {
+        match self.operand(FOR + 1, "lambda") {
+            Result::Ok(body) => Result::Ok(Node::Lambda(params, Box::new(body))),
+            res => res
+        }
+    }
+    fn parse_list(&mut self) -> Result<Vec<Node>> {
+        let mut items = Vec::new();
+        if let Some(Token::CloseBracket) = self.feed.peek() {
+            self.feed.next();
+            return Result::Ok(items)
+        }
+        loop {
+            match self.parse_inner(0) {
+                Result::None => {
+                    let err = format!("list expect item");
+                    return Result::Incomplete(err)
+                }
+                Result::Err(err) => return Result::Err(err),
+                Result::Incomplete(err) => return Result::Incomplete(err),
+                Result::Ok(node) => items.push(node)
+            }
+            match self.feed.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::CloseBracket) => return Result::Ok(items),
+                Some(tok) => {
+                    let err = format!("expect comma or close bracket, found {}", tok);
+                    return Result::Err(err)
+                }
+                None => {
+                    let err = format!("expect close bracket");
+                    return Result::Incomplete(err)
+                }
+            }
+        }
+    }
+    fn parse_args(&mut self) -> Result<Vec<Node>> {
+        let mut args = Vec::new();
+        if let Some(Token::CloseParen) = self.feed.peek() {
+            self.feed.next();
+            return Result::Ok(args)
+        }
+        loop {
+            match self.parse_inner(0) {
+                Result::None => {
+                    let err = format!("call expect argument");
+                    return Result::Incomplete(err)
+                }
+                Result::Err(err) => return Result::Err(err),
+                Result::Incomplete(err) => return Result::Incomplete(err),
+                Result::Ok(node) => args.push(node)
+            }
+            match self.feed.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::CloseParen) => return Result::Ok(args),
+                Some(tok) => {
+                    let err = format!("expect comma or close parentheses, found {}", tok);
+                    return Result::Err(err)
+                }
+                None => {
+                    let err = format!("expect close parentheses");
+                    return Result::Incomplete(err)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// `src` as an expression, which must parse.
+    pub fn parse(src: &str) -> Node {
+        match Parser::new(Lexer::new(src.chars())).parse() {
+            Result::Ok(node) => node,
+            Result::None => panic!("no expression in {:?}", src),
+            Result::Err(err) | Result::Incomplete(err) => panic!("{:?}: {}", src, err),
+        }
+    }
+
+    /// `src` as a statement, which must parse.
+    pub fn stmt(src: &str) -> Stmt {
+        match Parser::new(Lexer::new(src.chars())).parse_stmt() {
+            Result::Ok(stmt) => stmt,
+            Result::None => panic!("no statement in {:?}", src),
+            Result::Err(err) | Result::Incomplete(err) => panic!("{:?}: {}", src, err),
+        }
+    }
+
+    fn error(src: &str) -> String {
+        match Parser::new(Lexer::new(src.chars()).lenient()).parse() {
+            Result::Err(err) | Result::Incomplete(err) => err,
+            _ => panic!("{:?} parsed", src),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_characters() {
+        assert_eq!(error("$"), "unknow character $");
+        assert_eq!(error("1 $ 2"), "unknow character $");
+        assert_eq!(error("f(1, 2 @)"), "unknow character @");
+    }
+
+    fn incomplete(src: &str) -> bool {
+        matches!(Parser::new(Lexer::new(src.chars()).lenient()).parse_stmt(), Result::Incomplete(..))
+    }
+
+    #[test]
+    fn input_that_only_lacks_its_end_is_incomplete() {
+        for src in ["(1 +", "f(1,", "f(1", "x =", "f(x) =", "-", "2 **", "use a::",
+                    "if a then", "if a then b else", "a ?", "a ? b :", "x for x in", "[1, 2", "xs[", "|x|", "|x"] {
+            assert!(incomplete(src), "{}", src);
+        }
+        for src in ["1 )", "(1 2", "f(1 2", "1 +* 2", "x = = 1", ""] {
+            assert!(!incomplete(src), "{}", src);
+        }
+    }
+
+    #[test]
+    fn conditions_bind_below_comparisons() {
+        assert_eq!(parse("1 || 2 && 3").to_string(), "(LogicOr 1 (LogicAnd 2 3))");
+        assert_eq!(parse("a + 1 < b && c == d").to_string(), "(LogicAnd (Lt (Add a 1) b) (Eq c d))");
+        assert_eq!(parse("0 ? 1 : 2 ? 3 : 4").to_string(), "(If 0 1 (If 2 3 4))");
+        assert_eq!(parse("a ? b : c + 1").to_string(), "(If a b (Add c 1))");
+        assert_eq!(parse("if a then b else c + 1").to_string(), "(If a b (Add c 1))");
+        assert_eq!(parse("if a then if b then 1 else 2 else 3").to_string(), "(If a (If b 1 2) 3)");
+        assert_eq!(parse("(a ? b : c) * 2").to_string(), "(Mul (If a b c) 2)");
+    }
+
+    #[test]
+    fn loops_take_the_whole_body_and_one_for() {
+        assert_eq!(parse("x + 1 for x in 1..n").to_string(), "(For x (Range 1 n) (Add x 1))");
+        assert_eq!(parse("a ? b : c for c in s").to_string(), "(For c s (If a b c))");
+        assert_eq!(parse("sum(x for x in s) + 1").to_string(), "(Add (Call sum (For x s x)) 1)");
+        assert_eq!(parse("(x + y for x in 1..=2) for y in 0..2").to_string(),
+                   "(For y (Range 0 2) (For x (RangeIncl 1 2) (Add x y)))");
+        assert_eq!(error("x + y for x in 1..=2 for y in 0..2"), "for expect one loop, put the inner one in parentheses");
+    }
 }
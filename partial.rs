@@ -0,0 +1,159 @@
+use crate::env::Env;
+use crate::eval;
+use crate::fold::Folder;
+use crate::node::{Node, Value, UnaryOp, BinaryOp};
+
+struct PartialEval<'a> {
+    env: &'a Env,
+    /// Loop variables and lambda parameters in reach, never substituted.
+    bound: Vec<String>
+}
+
+impl Folder for PartialEval<'_> {
+    fn fold_var(&mut self, name: String) -> Node {
+        match self.env.get(&name) {
+            Some(val) if !self.bound.contains(&name) => Node::Val(val.clone()),
+            _ => Node::Var(name),
+        }
+    }
+    fn fold_unary(&mut self, op: UnaryOp, node: Node) -> Node {
+        match self.fold_node(node) {
+            Node::Val(val) if let Ok(res) = op.apply(val.clone()) => Node::Val(res),
+            node => Node::Unary(op, Box::new(node)),
+        }
+    }
+    fn fold_binary(&mut self, op: BinaryOp, left: Node, right: Node) -> Node {
+        let left = self.fold_node(left);
+        // `0 && x` and `1 || x` are known whatever `x` is
+        let truth = match &left {
+            Node::Val(val) => val.truthy().ok(),
+            _ => None,
+        };
+        match (&op, truth) {
+            (BinaryOp::LogicAnd, Some(false)) => return Node::Val(Value::Num(0)),
+            (BinaryOp::LogicOr, Some(true)) => return Node::Val(Value::Num(1)),
+            _ => {}
+        }
+        match (left, self.fold_node(right)) {
+            (Node::Val(left_val), Node::Val(right_val))
+                if let Ok(res) = eval::binary(self.env.settings.mode, &op, left_val.clone(), right_val.clone()) => Node::Val(res),
+            (left, right) => Node::Binary(op, Box::new(left), Box::new(right)),
+        }
+    }
+    fn fold_call(&mut self, name: String, args: Vec<Node>) -> Node {
+        let args = args
+            .into_iter()
+            .map(|arg| self.fold_node(arg))
+            .collect::<Vec<_>>();
+        let vals = args
+            .iter()
+            .map(|arg| match arg {
+                Node::Val(val) => Some(val.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<Value>>>();
+        if self.bound.contains(&name) {
+            return Node::Call(name, args)
+        }
+        match vals.and_then(|vals| eval::call(self.env, &name, vals, 0).ok()) {
+            Some(res) => Node::Val(res),
+            None => Node::Call(name, args),
+        }
+    }
+    fn fold_for(&mut self, var: String, seq: Node, body: Node) -> Node {
+        let seq = self.fold_node(seq);
+        self.bound.push(var.clone());
+        let body = self.fold_node(body);
+        self.bound.pop();
+        Node::For(var, Box::new(seq), Box::new(body))
+    }
+    fn fold_lambda(&mut self, params: Vec<String>, body: Node) -> Node {
+        let len = self.bound.len();
+        self.bound.extend(params.iter().cloned());
+        let body = self.fold_node(body);
+        self.bound.truncate(len);
+        Node::Lambda(params, Box::new(body))
+    }
+    fn fold_list(&mut self, items: Vec<Node>) -> Node {
+        let items = items
+            .into_iter()
+            .map(|item| self.fold_node(item))
+            .collect::<Vec<_>>();
+        let vals = items
+            .iter()
+            .map(|item| match item {
+                Node::Val(val) => Some(val.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<Value>>>();
+        match vals {
+            Some(vals) => Node::Val(Value::list(vals)),
+            None => Node::List(items),
+        }
+    }
+    fn fold_index(&mut self, node: Node, index: Node) -> Node {
+        match (self.fold_node(node), self.fold_node(index)) {
+            (Node::Val(val), Node::Val(index)) if let Ok(res) = val.index(&index) => Node::Val(res),
+            (node, index) => Node::Index(Box::new(node), Box::new(index)),
+        }
+    }
+    fn fold_if(&mut self, cond: Node, then: Node, other: Node) -> Node {
+        match self.fold_node(cond) {
+            Node::Val(val) if let Ok(truth) = val.truthy() => match truth {
+                true => self.fold_node(then),
+                false => self.fold_node(other),
+            },
+            cond => Node::If(Box::new(cond), Box::new(self.fold_node(then)), Box::new(self.fold_node(other))),
+        }
+    }
+}
+
+impl Node {
+    /// Substitute the variables bound in `env` and fold every subtree whose
+    /// operands are all known, leaving the rest as a residual tree.
+    ///
+    /// `(a + 2*3) * b` with `b = 2` becomes `(a + 6) * 2`. Subtrees that fail
+    /// to fold are kept as they are, so the error surfaces on full evaluation.
+    pub fn partial_eval(self, env: &Env) -> Node {
+        env.reset_steps();
+        PartialEval { env, bound: Vec::new() }.fold_node(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::Env;
+    use crate::node::Value;
+    use crate::parser::tests::parse;
+
+    fn partial(env: &Env, src: &str) -> String {
+        parse(src).partial_eval(env).to_string()
+    }
+
+    #[test]
+    fn folds_known_subtrees() {
+        let mut env = Env::new();
+        env.bind("b", Value::Num(2));
+        assert_eq!(partial(&env, "(a + 2*3) * b"), "(Mul (Add a 6) 2)");
+        assert_eq!(partial(&env, "-b ** 3 - a"), "(Sub -8 a)");
+        assert_eq!(partial(&env, "max(b, 5) - min(a, b)"), "(Sub 5 (Call min a 2))");
+    }
+
+    #[test]
+    fn keeps_failing_subtrees() {
+        let env = Env::new();
+        assert_eq!(partial(&env, "1 / 0 + a"), "(Add (Div 1 0) a)");
+        assert_eq!(partial(&env, "[1, 2][5] * a"), "(Mul (Index (List 1 2) 5) a)");
+    }
+
+    #[test]
+    fn leaves_unbound_names() {
+        let mut env = Env::new();
+        env.bind("x", Value::Num(5));
+        assert_eq!(partial(&env, "x + y"), "(Add 5 y)");
+        assert_eq!(partial(&env, "-y"), "(Neg y)");
+        assert_eq!(partial(&env, "2 * 3"), "6");
+        assert_eq!(partial(&env, "sum(x * y for x in 1..n)"), "(Call sum (For x (Range 1 n) (Mul x y)))");
+        assert_eq!(partial(&env, "|x| x + y"), "(Lambda x (Add x y))");
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// Integers from `start` towards `end` by `step`. `end` itself is included
+/// only for `a..=b`.
+#[derive(Clone, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+    pub inclusive: bool
+}
+
+impl Range {
+    pub fn new(start: i64, end: i64, inclusive: bool) -> Self {
+        Self {
+            start,
+            end,
+            step: 1,
+            inclusive
+        }
+    }
+    /// Number of items, without counting them one by one. Saturates at
+    /// `usize::MAX`.
+    pub fn len(&self) -> usize {
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        let end = match (self.inclusive, step > 0) {
+            (false, _) => end,
+            (true, true) => end + 1,
+            (true, false) => end - 1,
+        };
+        let span = match step > 0 {
+            true => end - start,
+            false => start - end,
+        };
+        match span > 0 {
+            true => usize::try_from((span + step.abs() - 1) / step.abs()).unwrap_or(usize::MAX),
+            false => 0,
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = i64> {
+        let Range { end, step, inclusive, .. } = *self;
+        let mut next = Some(self.start);
+        std::iter::from_fn(move || {
+            let n = next?;
+            let more = match (step > 0, inclusive) {
+                (true, true) => n <= end,
+                (true, false) => n < end,
+                (false, true) => n >= end,
+                (false, false) => n > end,
+            };
+            if !more {
+                return None
+            }
+            next = n.checked_add(step);
+            Some(n)
+        })
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        match self.step {
+            1 => write!(f, "{}{}{}", self.start, dots, self.end),
+            step => write!(f, "step({}{}{}, {})", self.start, dots, self.end, step),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::eval::tests::run;
+
+    fn items(range: &Range) -> Vec<i64> {
+        range.iter().collect()
+    }
+
+    #[test]
+    fn counts_without_walking() {
+        for (start, end, step, inclusive) in [(1, 5, 1, false), (1, 5, 1, true), (5, 1, 1, false), (1, 10, 3, false),
+                                              (1, 10, 3, true), (10, 1, -3, true), (10, 1, -1, false), (1, 10, -1, false), (3, 3, 1, true)] {
+            let range = Range { start, end, step, inclusive };
+            assert_eq!(range.len(), items(&range).len(), "{}", range);
+        }
+        assert_eq!(Range::new(i64::MIN, i64::MAX, true).len(), usize::MAX);
+    }
+
+    #[test]
+    fn stops_at_the_ends_of_i64() {
+        assert_eq!(items(&Range::new(i64::MAX - 1, i64::MAX, true)), [i64::MAX - 1, i64::MAX]);
+        assert_eq!(items(&Range { start: i64::MIN + 1, end: i64::MIN, step: -1, inclusive: true }), [i64::MIN + 1, i64::MIN]);
+        assert_eq!(items(&Range { start: 0, end: i64::MAX, step: i64::MAX, inclusive: true }), [0, i64::MAX]);
+    }
+
+    #[test]
+    fn steps_and_folds() {
+        let mut env = Env::new();
+        for (src, res) in [("1..5", "1..5"), ("step(1..=10, 3)", "step(1..=10, 3)"), ("sum(step(1..=10, 3))", "22"),
+                           ("sum(step(10..=1, -3))", "22"), ("x for x in step(1..10, -1)", "[]"), ("x for x in 5..1", "[]"),
+                           ("sum(1..=100)", "5050"), ("prod(1..=5)", "120"), ("sum([])", "0"), ("prod([])", "1"), ("len(step(1..=10, 3))", "4"),
+                           ("count(x % 2 == 0 for x in 1..10)", "4"), ("any(x > 3 for x in 1..5)", "1"), ("all(x > 3 for x in 1..5)", "0"),
+                           ("sum(sum(x * y for y in 1..=3) for x in 1..=2)", "18"),
+                           ("(x + y for x in 1..=2) for y in 0..2", "[[1, 2], [2, 3]]")] {
+            assert_eq!(run(&mut env, src), res, "{}", src);
+        }
+        assert_eq!(run(&mut env, "step(1..10, 0)"), "error: not supported: step of 0");
+    }
+}
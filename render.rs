@@ -0,0 +1,479 @@
+use std::fmt::Write;
+
+use crate::infix;
+use crate::matrix::Matrix;
+use crate::node::{Node, Value, UnaryOp, BinaryOp, PrefixOp, PostfixOp};
+
+// Leaves and calls never need grouping, nor do fractions, which are
+// delimited by their bar. Everything else follows the infix rules, except
+// that factorial and power bases take anything non-atomic in parentheses,
+// as readers expect them to bind tighter than any other operator.
+fn is_atom(node: &Node) -> bool {
+    match node {
+        Node::Val(Value::Num(n)) => *n >= 0,
+        Node::Val(Value::Float(x)) => x.is_sign_positive(),
+        Node::Val(Value::Complex(..) | Value::Func(..)) => false,
+        Node::Val(..) | Node::Var(..) | Node::Call(..) | Node::List(..) | Node::Index(..) => true,
+        _ => false,
+    }
+}
+
+// A list of lists of one length, as `[[1, 2], [3, 4]]`, shows as a matrix.
+fn rows(items: &[Node]) -> Option<Vec<&[Node]>> {
+    let rows = items
+        .iter()
+        .map(|item| match item {
+            Node::List(row) if !row.is_empty() => Some(row.as_slice()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let cols = rows.first()?.len();
+    rows.iter().all(|row| row.len() == cols).then_some(rows)
+}
+
+fn matrix_node(matrix: &Matrix) -> Node {
+    let rows = matrix.items
+        .chunks(matrix.cols)
+        .map(|row| Node::List(row.iter().cloned().map(Node::Val).collect()))
+        .collect();
+    Node::List(rows)
+}
+
+fn needs_parens(node: &Node, power: u8, next: Option<u8>) -> bool {
+    match node {
+        Node::Binary(BinaryOp::Div, ..) => false,
+        Node::Unary(op, ..) if let Some(..) = Option::<PostfixOp>::from(op) => false,
+        node => infix::needs_parens(node, power, next),
+    }
+}
+
+/// LaTeX math-mode source for a tree, e.g. `\frac{a}{b}`, `x^{2}`, `n!`.
+pub fn latex(node: &Node) -> String {
+    let mut out = String::new();
+    write_latex(&mut out, node, 0, None);
+    out
+}
+
+fn latex_name(name: &str) -> String {
+    let name = name.replace('_', r"\_");
+    if name.chars().count() == 1 { name } else { format!(r"\mathit{{{}}}", name) }
+}
+
+fn latex_op(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+
+        BinaryOp::Mul => r"\cdot",
+        BinaryOp::Div => r"\div",
+        BinaryOp::Mod => r"\bmod",
+
+        BinaryOp::Pow => "^",
+
+        BinaryOp::BitAnd => r"\mathbin{\&}",
+        BinaryOp::BitOr => r"\mathbin{|}",
+        BinaryOp::BitXor => r"\oplus",
+
+        BinaryOp::ShiftLeft => r"\ll",
+        BinaryOp::ShiftRight => r"\gg",
+
+        BinaryOp::Eq => "=",
+        BinaryOp::Ne => r"\neq",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => r"\leq",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => r"\geq",
+
+        BinaryOp::LogicAnd => r"\land",
+        BinaryOp::LogicOr => r"\lor",
+
+        BinaryOp::Range => r"\mathrel{..}",
+        BinaryOp::RangeIncl => r"\mathrel{..=}",
+
+        BinaryOp::Concat => r"\mathbin{+\!\!+}",
+    }
+}
+
+fn write_latex_group(out: &mut String, node: &Node, parens: bool) {
+    if parens {
+        out.push_str(r"\left(");
+        write_latex(out, node, 0, None);
+        out.push_str(r"\right)");
+    }
+    else {
+        write_latex(out, node, 0, None)
+    }
+}
+
+fn write_latex(out: &mut String, node: &Node, power: u8, next: Option<u8>) {
+    if needs_parens(node, power, next) {
+        return write_latex_group(out, node, true)
+    }
+    match node {
+        Node::Val(val @ (Value::Num(..) | Value::Float(..))) => write!(out, "{}", val).unwrap(),
+        Node::Val(Value::Complex(z)) => write!(out, "{}", z).unwrap(),
+        Node::Val(Value::Str(s)) => write!(out, r#"\text{{"{}"}}"#, s).unwrap(),
+        Node::Val(Value::Range(range)) => write!(out, r"\text{{{}}}", range).unwrap(),
+        Node::Val(Value::List(items)) => write_latex(out, &Node::List(items.iter().cloned().map(Node::Val).collect()), power, next),
+        Node::Val(Value::Matrix(matrix)) => write_latex(out, &matrix_node(matrix), power, next),
+        Node::Val(Value::Func(closure)) => write_latex(out, &closure.lambda(), power, next),
+        Node::Var(name) => out.push_str(&latex_name(name)),
+
+        Node::Unary(UnaryOp::Fac, node) => {
+            write_latex_group(out, node, !is_atom(node));
+            out.push('!')
+        }
+        Node::Unary(op, node) => {
+            let sym = match op {
+                UnaryOp::Neg => "-",
+                UnaryOp::BitNot => r"\sim ",
+                UnaryOp::LogicNot => r"\lnot ",
+                UnaryOp::Fac => unreachable!(),
+            };
+            out.push_str(sym);
+            let power = Option::<PrefixOp>::from(op).unwrap().binding_power();
+            write_latex(out, node, power, next)
+        }
+
+        Node::Binary(BinaryOp::Div, left, right) => {
+            out.push_str(r"\frac{");
+            write_latex(out, left, 0, None);
+            out.push_str("}{");
+            write_latex(out, right, 0, None);
+            out.push('}')
+        }
+        Node::Binary(BinaryOp::Pow, left, right) => {
+            write_latex_group(out, left, !is_atom(left));
+            out.push_str("^{");
+            write_latex(out, right, 0, None);
+            out.push('}')
+        }
+        Node::Binary(op, left, right) => {
+            let (left_power, right_power) = op.binding_power();
+            write_latex(out, left, power, Some(left_power));
+            write!(out, " {} ", latex_op(op)).unwrap();
+            write_latex(out, right, right_power, next)
+        }
+
+        Node::Call(name, args) => {
+            write!(out, r"\operatorname{{{}}}\left(", name.replace('_', r"\_")).unwrap();
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ")
+                }
+                write_latex(out, arg, 0, None)
+            }
+            out.push_str(r"\right)")
+        }
+
+        Node::If(cond, then, other) => {
+            out.push_str(r"\begin{cases} ");
+            write_latex(out, then, 0, None);
+            out.push_str(r" & \text{if } ");
+            write_latex(out, cond, 0, None);
+            out.push_str(r" \\ ");
+            write_latex(out, other, 0, None);
+            out.push_str(r" & \text{otherwise} \end{cases}")
+        }
+
+        Node::For(var, seq, body) => {
+            write_latex(out, body, 0, None);
+            write!(out, r" \;\text{{for}}\; {} \in ", latex_name(var)).unwrap();
+            write_latex(out, seq, 0, None)
+        }
+
+        Node::List(items) if let Some(rows) = rows(items) => {
+            out.push_str(r"\begin{pmatrix} ");
+            for (i, row) in rows.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(r" \\ ")
+                }
+                for (j, item) in row.iter().enumerate() {
+                    if j > 0 {
+                        out.push_str(" & ")
+                    }
+                    write_latex(out, item, 0, None)
+                }
+            }
+            out.push_str(r" \end{pmatrix}")
+        }
+        Node::List(items) => {
+            out.push_str(r"\left[");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ")
+                }
+                write_latex(out, item, 0, None)
+            }
+            out.push_str(r"\right]")
+        }
+        Node::Index(node, index) => {
+            write_latex_group(out, node, !is_atom(node));
+            out.push_str("_{");
+            write_latex(out, index, 0, None);
+            out.push('}')
+        }
+        Node::Lambda(params, body) => {
+            let names = params
+                .iter()
+                .map(|param| latex_name(param))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match params.len() {
+                1 => write!(out, r"{} \mapsto ", names).unwrap(),
+                _ => write!(out, r"\left({}\right) \mapsto ", names).unwrap(),
+            }
+            write_latex(out, body, 0, None)
+        }
+    }
+}
+
+/// Presentation MathML for a tree, wrapped in a `<math>` element.
+pub fn mathml(node: &Node) -> String {
+    let mut out = String::new();
+    out.push_str(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#);
+    write_mathml(&mut out, node, 0, None);
+    out.push_str("</math>");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn mathml_op(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "&#x2212;",
+
+        BinaryOp::Mul => "&#x22C5;",
+        BinaryOp::Div => "&#xF7;",
+        BinaryOp::Mod => "mod",
+
+        BinaryOp::Pow => "^",
+
+        BinaryOp::BitAnd => "&amp;",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "&#x2295;",
+
+        BinaryOp::ShiftLeft => "&#x226A;",
+        BinaryOp::ShiftRight => "&#x226B;",
+
+        BinaryOp::Eq => "=",
+        BinaryOp::Ne => "&#x2260;",
+        BinaryOp::Lt => "&lt;",
+        BinaryOp::Le => "&#x2264;",
+        BinaryOp::Gt => "&gt;",
+        BinaryOp::Ge => "&#x2265;",
+
+        BinaryOp::LogicAnd => "&#x2227;",
+        BinaryOp::LogicOr => "&#x2228;",
+
+        BinaryOp::Range => "..",
+        BinaryOp::RangeIncl => "..=",
+
+        BinaryOp::Concat => "++",
+    }
+}
+
+fn write_mathml_group(out: &mut String, node: &Node, parens: bool) {
+    if parens {
+        out.push_str("<mrow><mo>(</mo>");
+        write_mathml(out, node, 0, None);
+        out.push_str("<mo>)</mo></mrow>");
+    }
+    else {
+        write_mathml(out, node, 0, None)
+    }
+}
+
+fn write_mathml(out: &mut String, node: &Node, power: u8, next: Option<u8>) {
+    if needs_parens(node, power, next) {
+        return write_mathml_group(out, node, true)
+    }
+    match node {
+        Node::Val(Value::Num(n)) if *n < 0 => write!(out, "<mrow><mo>&#x2212;</mo><mn>{}</mn></mrow>", n.unsigned_abs()).unwrap(),
+        Node::Val(Value::Float(x)) if x.is_sign_negative() => {
+            write!(out, "<mrow><mo>&#x2212;</mo><mn>{}</mn></mrow>", Value::Float(-x)).unwrap()
+        }
+        Node::Val(val @ (Value::Num(..) | Value::Float(..))) => write!(out, "<mn>{}</mn>", val).unwrap(),
+        Node::Val(Value::Complex(z)) => {
+            out.push_str("<mrow>");
+            match (z.re == 0.0, z.im.is_sign_negative()) {
+                (false, negative) => {
+                    write!(out, "<mn>{}</mn>", z.re).unwrap();
+                    out.push_str(if negative { "<mo>&#x2212;</mo>" } else { "<mo>+</mo>" })
+                }
+                (true, true) => out.push_str("<mo>&#x2212;</mo>"),
+                (true, false) => {}
+            }
+            write!(out, "<mn>{}</mn><mi>i</mi></mrow>", z.im.abs()).unwrap()
+        }
+        Node::Val(Value::Str(s)) => write!(out, "<ms>{}</ms>", escape(s)).unwrap(),
+        Node::Val(Value::Range(range)) => write!(out, "<mtext>{}</mtext>", range).unwrap(),
+        Node::Val(Value::List(items)) => write_mathml(out, &Node::List(items.iter().cloned().map(Node::Val).collect()), power, next),
+        Node::Val(Value::Matrix(matrix)) => write_mathml(out, &matrix_node(matrix), power, next),
+        Node::Val(Value::Func(closure)) => write_mathml(out, &closure.lambda(), power, next),
+        Node::Var(name) => write!(out, "<mi>{}</mi>", escape(name)).unwrap(),
+
+        Node::Unary(UnaryOp::Fac, node) => {
+            out.push_str("<mrow>");
+            write_mathml_group(out, node, !is_atom(node));
+            out.push_str("<mo>!</mo></mrow>")
+        }
+        Node::Unary(op, node) => {
+            let sym = match op {
+                UnaryOp::Neg => "&#x2212;",
+                UnaryOp::BitNot => "~",
+                UnaryOp::LogicNot => "&#xAC;",
+                UnaryOp::Fac => unreachable!(),
+            };
+            write!(out, "<mrow><mo>{}</mo>", sym).unwrap();
+            let power = Option::<PrefixOp>::from(op).unwrap().binding_power();
+            write_mathml(out, node, power, next);
+            out.push_str("</mrow>")
+        }
+
+        Node::Binary(BinaryOp::Div, left, right) => {
+            out.push_str("<mfrac>");
+            write_mathml_group(out, left, false);
+            write_mathml_group(out, right, false);
+            out.push_str("</mfrac>")
+        }
+        Node::Binary(BinaryOp::Pow, left, right) => {
+            out.push_str("<msup>");
+            write_mathml_group(out, left, !is_atom(left));
+            write_mathml_group(out, right, false);
+            out.push_str("</msup>")
+        }
+        Node::Binary(op, left, right) => {
+            let (left_power, right_power) = op.binding_power();
+            out.push_str("<mrow>");
+            write_mathml(out, left, power, Some(left_power));
+            write!(out, "<mo>{}</mo>", mathml_op(op)).unwrap();
+            write_mathml(out, right, right_power, next);
+            out.push_str("</mrow>")
+        }
+
+        Node::Call(name, args) => {
+            write!(out, "<mrow><mi>{}</mi><mo>&#x2061;</mo><mrow><mo>(</mo>", escape(name)).unwrap();
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("<mo>,</mo>")
+                }
+                write_mathml(out, arg, 0, None)
+            }
+            out.push_str("<mo>)</mo></mrow></mrow>")
+        }
+
+        Node::If(cond, then, other) => {
+            out.push_str("<mrow><mo>{</mo><mtable><mtr><mtd>");
+            write_mathml(out, then, 0, None);
+            out.push_str("</mtd><mtd><mtext>if&#xA0;</mtext>");
+            write_mathml(out, cond, 0, None);
+            out.push_str("</mtd></mtr><mtr><mtd>");
+            write_mathml(out, other, 0, None);
+            out.push_str("</mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable></mrow>")
+        }
+
+        Node::For(var, seq, body) => {
+            out.push_str("<mrow>");
+            write_mathml(out, body, 0, None);
+            write!(out, "<mtext>&#xA0;for&#xA0;</mtext><mi>{}</mi><mo>&#x2208;</mo>", escape(var)).unwrap();
+            write_mathml(out, seq, 0, None);
+            out.push_str("</mrow>")
+        }
+
+        Node::List(items) if let Some(rows) = rows(items) => {
+            out.push_str("<mrow><mo>(</mo><mtable>");
+            for row in rows {
+                out.push_str("<mtr>");
+                for item in row {
+                    out.push_str("<mtd>");
+                    write_mathml(out, item, 0, None);
+                    out.push_str("</mtd>")
+                }
+                out.push_str("</mtr>")
+            }
+            out.push_str("</mtable><mo>)</mo></mrow>")
+        }
+        Node::List(items) => {
+            out.push_str("<mrow><mo>[</mo>");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("<mo>,</mo>")
+                }
+                write_mathml(out, item, 0, None)
+            }
+            out.push_str("<mo>]</mo></mrow>")
+        }
+        Node::Index(node, index) => {
+            out.push_str("<msub>");
+            write_mathml_group(out, node, !is_atom(node));
+            out.push_str("<mrow>");
+            write_mathml(out, index, 0, None);
+            out.push_str("</mrow></msub>")
+        }
+        Node::Lambda(params, body) => {
+            out.push_str("<mrow>");
+            if params.len() != 1 {
+                out.push_str("<mo>(</mo>")
+            }
+            for (i, param) in params.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("<mo>,</mo>")
+                }
+                write!(out, "<mi>{}</mi>", escape(param)).unwrap()
+            }
+            if params.len() != 1 {
+                out.push_str("<mo>)</mo>")
+            }
+            out.push_str("<mo>&#x21A6;</mo>");
+            write_mathml(out, body, 0, None);
+            out.push_str("</mrow>")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+
+    fn tex(src: &str) -> String {
+        latex(&parse(src))
+    }
+
+    #[test]
+    fn latex_groups_like_the_infix_form() {
+        assert_eq!(tex("(a + b) / c ** 2"), r"\frac{a + b}{c^{2}}");
+        assert_eq!(tex("(a + b) * c"), r"\left(a + b\right) \cdot c");
+        assert_eq!(tex("2 ** (x + 1)"), r"2^{x + 1}");
+        assert_eq!(tex("(-a)!"), r"\left(-a\right)!");
+        assert_eq!(tex("a & ~b | c % 2"), r"a \mathbin{\&} \sim b \mathbin{|} c \bmod 2");
+        assert_eq!(tex("a && !b || c"), r"a \land \lnot b \lor c");
+    }
+
+    #[test]
+    fn latex_names_and_calls() {
+        assert_eq!(tex("a_1 * x"), r"\mathit{a\_1} \cdot x");
+        assert_eq!(tex("max(a, 2)"), r"\operatorname{max}\left(a, 2\right)");
+        assert_eq!(tex("a_1 <= xs[i]"), r"\mathit{a\_1} \leq \mathit{xs}_{i}");
+        assert_eq!(tex("|x| x + 1"), r"x \mapsto x + 1");
+        assert_eq!(tex("|x, y| x"), r"\left(x, y\right) \mapsto x");
+        assert_eq!(tex("[[1, 2], [3, 4]]"), r"\begin{pmatrix} 1 & 2 \\ 3 & 4 \end{pmatrix}");
+        assert_eq!(tex("1 + 2i"), "1 + 2i");
+    }
+
+    #[test]
+    fn mathml_wraps_in_math() {
+        let math = |src| mathml(&parse(src));
+        assert_eq!(math("n!"), r#"<math xmlns="http://www.w3.org/1998/Math/MathML"><mrow><mi>n</mi><mo>!</mo></mrow></math>"#);
+        assert_eq!(math("x ** 2 + 1"), concat!(
+            r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#,
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1</mn></mrow></math>",
+        ));
+        assert!(math("-a / 2").contains("<mfrac><mrow><mo>&#x2212;</mo><mi>a</mi></mrow><mn>2</mn></mfrac>"));
+        assert!(math("if a then b else c").contains("<mtext>otherwise</mtext>"));
+    }
+}
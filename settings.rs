@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::node::Value;
+
+#[derive(Clone, Copy)]
+pub enum Radix {
+    Bin,
+    Oct,
+    Dec,
+    Hex,
+}
+
+/// `Exact` keeps arithmetic on integers in integers, `Float` carries it out
+/// in floating point. `Complex` is `Float` where square roots and fractional
+/// powers of negative numbers are complex rather than NaN. Bitwise operators
+/// and factorial are integer-only in every mode.
+#[derive(Clone, Copy)]
+pub enum Mode {
+    Exact,
+    Float,
+    Complex,
+}
+
+/// How complex numbers print: `1+1i`, or magnitude and angle `1.414∠0.785`.
+#[derive(Clone, Copy)]
+pub enum Form {
+    Rect,
+    Polar,
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    pub radix: Radix,
+    pub precision: Option<usize>,
+    pub mode: Mode,
+    pub form: Form,
+    /// Session file rewritten after every REPL line.
+    pub autosave: Option<String>
+}
+
+/// A value printed with the radix, precision and complex form of some
+/// `Settings`.
+pub struct Shown<'a> {
+    val: &'a Value,
+    settings: &'a Settings
+}
+
+impl Radix {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let res = match name {
+            "bin" | "2" => Radix::Bin,
+            "oct" | "8" => Radix::Oct,
+            "dec" | "10" => Radix::Dec,
+            "hex" | "16" => Radix::Hex,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl fmt::Display for Radix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Radix::Bin => "bin",
+            Radix::Oct => "oct",
+            Radix::Dec => "dec",
+            Radix::Hex => "hex",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let res = match name {
+            "exact" => Mode::Exact,
+            "float" => Mode::Float,
+            "complex" => Mode::Complex,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mode::Exact => "exact",
+            Mode::Float => "float",
+            Mode::Complex => "complex",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Form {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let res = match name {
+            "rect" => Form::Rect,
+            "polar" => Form::Polar,
+
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Form::Rect => "rect",
+            Form::Polar => "polar",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            radix: Radix::Dec,
+            precision: None,
+            mode: Mode::Exact,
+            form: Form::Rect,
+            autosave: None
+        }
+    }
+    pub fn show<'a>(&'a self, val: &'a Value) -> Shown<'a> {
+        Shown {
+            val,
+            settings: self
+        }
+    }
+}
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.val, self.settings.radix, self.settings.precision) {
+            (Value::Num(n), radix, _) => {
+                let sign = if *n < 0 { "-" } else { "" };
+                let n = n.unsigned_abs();
+                match radix {
+                    Radix::Bin => write!(f, "{}0b{:b}", sign, n),
+                    Radix::Oct => write!(f, "{}0o{:o}", sign, n),
+                    Radix::Dec => write!(f, "{}{}", sign, n),
+                    Radix::Hex => write!(f, "{}0x{:x}", sign, n),
+                }
+            }
+            (Value::Float(x), _, Some(precision)) => write!(f, "{:.*}", precision, x),
+            (Value::Complex(z), _, precision) => {
+                let part = |x: f64| match precision {
+                    Some(precision) => format!("{:.*}", precision, x),
+                    None => x.to_string(),
+                };
+                match (self.settings.form, z.re == 0.0, z.im.is_sign_negative()) {
+                    (Form::Polar, ..) => write!(f, "{}∠{}", part(z.abs()), part(z.arg())),
+                    (Form::Rect, true, _) => write!(f, "{}i", part(z.im)),
+                    (Form::Rect, false, true) => write!(f, "{}-{}i", part(z.re), part(-z.im)),
+                    (Form::Rect, false, false) => write!(f, "{}+{}i", part(z.re), part(z.im)),
+                }
+            }
+            (Value::List(items), ..) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}", self.settings.show(item))?
+                }
+                write!(f, "]")
+            }
+            (Value::Matrix(matrix), ..) => {
+                write!(f, "[")?;
+                for (i, row) in matrix.rows().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}", self.settings.show(&row))?
+                }
+                write!(f, "]")
+            }
+            (val, ..) => write!(f, "{}", val),
+        }
+    }
+}
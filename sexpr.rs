@@ -0,0 +1,314 @@
+use std::iter::Peekable;
+
+use crate::complex::Complex;
+use crate::lexer::CharIterator;
+use crate::node::{Node, Value, UnaryOp, BinaryOp};
+use crate::range::Range;
+
+/// Reads the S-expression form printed by `Display for Node` back into
+/// trees, one per top-level form. Ranges and complex numbers are atoms; a
+/// stepped range, a list, a matrix or a function value is printed as the
+/// form that builds it.
+///
+/// ```text
+/// (Add 1 (Neg x))
+/// (Call max a "b")
+/// (Call sum 1..=5)
+/// ```
+pub struct Reader<T: CharIterator> {
+    feed: Peekable<T>
+}
+
+enum Atom {
+    Open,
+    Close,
+    Word(String),
+    Str(String)
+}
+
+impl <T: CharIterator> Reader<T> {
+    pub fn new(feed: T) -> Self {
+        Self {
+            feed: feed.peekable()
+        }
+    }
+    fn next_atom(&mut self) -> Option<Result<Atom, String>> {
+        while let Some(c) = self.feed.peek() {
+            if !c.is_whitespace() {
+                break
+            }
+            self.feed.next();
+        }
+        let atom = match self.feed.next()? {
+            '(' => Atom::Open,
+            ')' => Atom::Close,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match self.feed.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Some(Err(format!("unterminated string"))),
+                    }
+                }
+                Atom::Str(s)
+            }
+            c => {
+                let mut s = String::from(c);
+                while let Some(&c) = self.feed.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break
+                    }
+                    s.push(c);
+                    self.feed.next();
+                }
+                Atom::Word(s)
+            }
+        };
+        Some(Ok(atom))
+    }
+    fn expect_atom(&mut self) -> Result<Atom, String> {
+        match self.next_atom() {
+            Some(atom) => atom,
+            None => Err(format!("expect close parentheses")),
+        }
+    }
+    fn read_node(&mut self, atom: Atom) -> Result<Node, String> {
+        match atom {
+            Atom::Close => Err(format!("unexpected close parentheses")),
+            Atom::Str(s) => Ok(Node::Val(Value::Str(s))),
+            Atom::Word(word) => read_word(word),
+            Atom::Open => {
+                let head = match self.expect_atom()? {
+                    Atom::Word(head) => head,
+                    _ => return Err(format!("expect operator name")),
+                };
+                let mut args = Vec::new();
+                loop {
+                    match self.expect_atom()? {
+                        Atom::Close => break,
+                        atom => args.push(self.read_node(atom)?),
+                    }
+                }
+                build(head, args)
+            }
+        }
+    }
+}
+
+fn read_word(word: String) -> Result<Node, String> {
+    if let Ok(n) = word.parse::<i64>() {
+        return Ok(Node::Val(Value::Num(n)))
+    }
+    if let Some((start, end)) = word.split_once("..") {
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => (end, true),
+            None => (end, false),
+        };
+        if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+            return Ok(Node::Val(Value::Range(Range::new(start, end, inclusive))))
+        }
+    }
+    let mut chars = word.chars();
+    let first = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    // qualified names such as `finance.npv` keep their dots
+    let name = first && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if let (false, true, Ok(x)) = (first, word.contains('.'), word.parse::<f64>()) {
+        return Ok(Node::Val(Value::Float(x)))
+    }
+    if let (false, Some(z)) = (name, read_complex(&word)) {
+        return Ok(Node::Val(Value::Complex(z)))
+    }
+    if name {
+        return Ok(Node::Var(word))
+    }
+    Err(format!("unknow atom {}", word))
+}
+
+/// `2i`, `-0.5i`, `1+2i` or `NaN-infi`, as complex values print.
+fn read_complex(word: &str) -> Option<Complex> {
+    let body = word.strip_suffix('i')?;
+    // the sign of the imaginary part is the last one after the first character
+    let at = body
+        .char_indices()
+        .skip(1)
+        .filter(|&(_, c)| c == '+' || c == '-')
+        .last();
+    match at {
+        Some((at, _)) => Some(Complex::new(body[..at].parse().ok()?, body[at..].parse().ok()?)),
+        None => Some(Complex::new(0.0, body.parse().ok()?)),
+    }
+}
+
+fn build(head: String, mut args: Vec<Node>) -> Result<Node, String> {
+    if head == "Call" {
+        return match args.first() {
+            Some(Node::Var(..)) => {
+                let name = match args.remove(0) {
+                    Node::Var(name) => name,
+                    _ => unreachable!(),
+                };
+                Ok(Node::Call(name, args))
+            }
+            _ => Err(format!("Call expect function name")),
+        }
+    }
+    if head == "For" {
+        return match args.as_slice() {
+            [Node::Var(..), _, _] => {
+                let body = args.pop().unwrap();
+                let seq = args.pop().unwrap();
+                match args.pop() {
+                    Some(Node::Var(var)) => Ok(Node::For(var, Box::new(seq), Box::new(body))),
+                    _ => unreachable!(),
+                }
+            }
+            _ => Err(format!("For expect a name and 2 operands")),
+        }
+    }
+    if head == "Lambda" {
+        let body = match args.pop() {
+            Some(body) => body,
+            None => return Err(format!("Lambda expect a body")),
+        };
+        let params = args
+            .into_iter()
+            .map(|arg| match arg {
+                Node::Var(name) => Ok(name),
+                _ => Err(format!("Lambda expect names before the body")),
+            })
+            .collect::<Result<_, _>>()?;
+        return Ok(Node::Lambda(params, Box::new(body)))
+    }
+    if head == "List" {
+        return Ok(Node::List(args))
+    }
+    if head == "Index" {
+        if args.len() != 2 {
+            return Err(format!("Index expect 2 operands, found {}", args.len()))
+        }
+        let index = args.pop().unwrap();
+        let node = args.pop().unwrap();
+        return Ok(Node::Index(Box::new(node), Box::new(index)))
+    }
+    if head == "If" {
+        if args.len() != 3 {
+            return Err(format!("If expect 3 operands, found {}", args.len()))
+        }
+        let other = args.pop().unwrap();
+        let then = args.pop().unwrap();
+        let cond = args.pop().unwrap();
+        return Ok(Node::If(Box::new(cond), Box::new(then), Box::new(other)))
+    }
+    if let Some(op) = UnaryOp::from_name(&head) {
+        if args.len() != 1 {
+            return Err(format!("{} expect 1 operand, found {}", head, args.len()))
+        }
+        let node = args.pop().unwrap();
+        return Ok(Node::Unary(op, Box::new(node)))
+    }
+    if let Some(op) = BinaryOp::from_name(&head) {
+        if args.len() != 2 {
+            return Err(format!("{} expect 2 operands, found {}", head, args.len()))
+        }
+        let right = args.pop().unwrap();
+        let left = args.pop().unwrap();
+        return Ok(Node::Binary(op, Box::new(left), Box::new(right)))
+    }
+    Err(format!("unknow operator {}", head))
+}
+
+impl <T: CharIterator> Iterator for Reader<T> {
+    type Item = Result<Node, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = match self.next_atom()? {
+            Ok(atom) => self.read_node(atom),
+            Err(err) => Err(err),
+        };
+        Some(node)
+    }
+}
+
+/// Read a single tree from `src`.
+pub fn read(src: &str) -> Result<Node, String> {
+    let mut reader = Reader::new(src.chars());
+    let node = match reader.next() {
+        Some(node) => node?,
+        None => return Err(format!("expect expression")),
+    };
+    match reader.next() {
+        None => Ok(node),
+        Some(..) => Err(format!("reader expect end of input")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::infix::tests::Rng;
+    use crate::parser::tests::parse;
+
+    #[test]
+    fn reads_what_display_prints() {
+        for src in ["-x ** 2!", "f(a, -1, g())", "a << 2 | ~b % c", "if a then b else c for c in 1..=n", "[1, [x]][0]", "map(xs, |x, y| x ++ y)", "finance.npv(r, 2i)"] {
+            let node = parse(src);
+            assert_eq!(read(&node.to_string()).unwrap().to_string(), node.to_string());
+        }
+    }
+
+    #[test]
+    fn reads_every_value_back() {
+        let vals = [
+            Value::Num(-2), Value::Num(i64::MIN), Value::Str(format!("a (b)")),
+            Value::Float(2.5), Value::Float(-1.0), Value::Float(f64::NAN),
+            Value::Float(f64::INFINITY), Value::Float(f64::NEG_INFINITY),
+            Value::Range(Range::new(1, 5, false)), Value::Range(Range::new(-3, -1, true)),
+            Value::Range(Range { step: -3, ..Range::new(10, 1, true) }),
+            Value::List(vec![]), Value::List(vec![Value::Num(1), Value::List(vec![Value::Str(format!("a"))])]),
+            parse("|x, y| x ++ [y]").eval(&Env::new()).ok().unwrap(),
+            parse("[[1, 2], [3, 4.5]]").eval(&Env::new()).ok().unwrap(),
+            Value::Complex(Complex::new(1.0, 2.0)), Value::Complex(Complex::new(-1.5, -0.5)), Value::Complex(Complex::new(0.0, -2.0)),
+            Value::Complex(Complex::new(f64::NAN, f64::INFINITY)), Value::Complex(Complex::new(0.0, f64::NEG_INFINITY)),
+            Value::Complex(Complex::new(0.0, f64::NAN))
+        ];
+        for val in vals {
+            let node = read(&Node::Val(val.clone()).to_string()).unwrap();
+            assert_eq!(node.to_string(), Node::Val(val.clone()).to_string());
+            assert_eq!(node.eval(&Env::new()).ok().unwrap().to_string(), val.to_string());
+        }
+        assert_eq!(read("(Add 1 -2)").unwrap().to_string(), "(Add 1 -2)");
+    }
+
+    #[test]
+    fn reads_random_trees_back() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..5000 {
+            let node = rng.node(4);
+            assert_eq!(read(&node.to_string()).unwrap().to_string(), node.to_string());
+        }
+    }
+
+    #[test]
+    fn reads_one_tree_per_form() {
+        let nodes = Reader::new("(Add 1 2) x\n(List)".chars())
+            .map(|node| node.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(nodes, ["(Add 1 2)", "x", "(List)"]);
+    }
+
+    #[test]
+    fn reports_malformed_forms() {
+        let err = |src| read(src).err().unwrap();
+        assert_eq!(err(""), "expect expression");
+        assert_eq!(err("(Add 1"), "expect close parentheses");
+        assert_eq!(err("(Add 1 2 3)"), "Add expect 2 operands, found 3");
+        assert_eq!(err("(Foo 1)"), "unknow operator Foo");
+        assert_eq!(err("(Call 1 2)"), "Call expect function name");
+        assert_eq!(err("1 2"), "reader expect end of input");
+        assert_eq!(err("\"abc"), "unterminated string");
+        assert_eq!(err("$"), "unknow atom $");
+        assert_eq!(err("1..x"), "unknow atom 1..x");
+        assert_eq!(err("1+xi"), "unknow atom 1+xi");
+    }
+}
@@ -7,6 +7,7 @@ pub enum Token {
     Line,
 
     Plus,
+    PlusPlus,
     Dash,
 
     Star,
@@ -25,13 +26,52 @@ pub enum Token {
     LessLess,
     MoreMore,
 
+    Less,
+    More,
+    LessEqual,
+    MoreEqual,
+    EqualEqual,
+    ExcEqual,
+
+    AndAnd,
+    PipePipe,
+
+    Question,
+    Colon,
+
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
+
+    Comma,
+    Dot,
+    DotDot,
+    DotDotEqual,
+    ColonColon,
+
+    Equal,
 
     Num(i64),
+    Float(f64),
+    /// `2i`, `0.5i`: the imaginary part of a complex number.
+    Imag(f64),
     Str(String),
 
-    Name(String)
+    Name(String),
+
+    /// Character the lexer could not read, in lenient mode.
+    Unknown(char),
+    /// Literal the lexer could not read, such as an integer too large for
+    /// `i64`, and why.
+    Malformed(String, &'static str)
+}
+
+/// Character offsets of a token in its line, `end` exclusive.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
 }
 
 impl fmt::Display for Token {
@@ -42,6 +82,7 @@ impl fmt::Display for Token {
             Token::Line => ";",
 
             Token::Plus => "+",
+            Token::PlusPlus => "++",
             Token::Dash => "-",
 
             Token::Star  => "*",
@@ -61,14 +102,53 @@ impl fmt::Display for Token {
             Token::LessLess => "<<",
             Token::MoreMore => ">>",
 
+            Token::Less => "<",
+            Token::More => ">",
+            Token::LessEqual => "<=",
+            Token::MoreEqual => ">=",
+            Token::EqualEqual => "==",
+            Token::ExcEqual => "!=",
+
+            Token::AndAnd => "&&",
+            Token::PipePipe => "||",
+
+            Token::Question => "?",
+            Token::Colon => ":",
+
             Token::OpenParen  => "(",
             Token::CloseParen => ")",
+            Token::OpenBracket  => "[",
+            Token::CloseBracket => "]",
+
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::DotDotEqual => "..=",
+            Token::ColonColon => "::",
+
+            Token::Equal => "=",
 
             Token::Num(val) => return write!(f, "{}", val),
+            Token::Float(val) => return write!(f, "{}", val),
+            Token::Imag(val) => return write!(f, "{}i", val),
             Token::Str(val) => return write!(f, r#""{}""#, val),
 
-            Token::Name(name) => return write!(f, "{}", name)
+            Token::Name(name) => return write!(f, "{}", name),
+
+            Token::Unknown(c) => return write!(f, "{}", c),
+            Token::Malformed(text, _) => return write!(f, "{}", text)
         };
         write!(f, "{}", res)
     }
 }
+
+impl Token {
+    /// The error to report for a token the lexer could not read.
+    pub fn error(&self) -> Option<String> {
+        match self {
+            Token::Unknown(c) => Some(format!("unknow character {}", c)),
+            Token::Malformed(text, why) => Some(format!("{} {}", why, text)),
+            _ => None,
+        }
+    }
+}
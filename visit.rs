@@ -0,0 +1,156 @@
+use crate::node::{Node, Value, UnaryOp, BinaryOp};
+
+/// Read-only pass over a tree. Override the methods for the variants a pass
+/// cares about; the defaults walk into every child.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node)
+    }
+    fn visit_val(&mut self, _val: &Value) {}
+    fn visit_var(&mut self, _name: &str) {}
+    fn visit_unary(&mut self, op: &UnaryOp, node: &Node) {
+        walk_unary(self, op, node)
+    }
+    fn visit_binary(&mut self, op: &BinaryOp, left: &Node, right: &Node) {
+        walk_binary(self, op, left, right)
+    }
+    fn visit_call(&mut self, name: &str, args: &[Node]) {
+        walk_call(self, name, args)
+    }
+    fn visit_if(&mut self, cond: &Node, then: &Node, other: &Node) {
+        walk_if(self, cond, then, other)
+    }
+    fn visit_for(&mut self, var: &str, seq: &Node, body: &Node) {
+        walk_for(self, var, seq, body)
+    }
+    fn visit_list(&mut self, items: &[Node]) {
+        walk_list(self, items)
+    }
+    fn visit_index(&mut self, node: &Node, index: &Node) {
+        walk_index(self, node, index)
+    }
+    fn visit_lambda(&mut self, params: &[String], body: &Node) {
+        walk_lambda(self, params, body)
+    }
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match node {
+        Node::Val(val) => visitor.visit_val(val),
+        Node::Var(name) => visitor.visit_var(name),
+        Node::Unary(op, node) => visitor.visit_unary(op, node),
+        Node::Binary(op, left, right) => visitor.visit_binary(op, left, right),
+        Node::Call(name, args) => visitor.visit_call(name, args),
+        Node::If(cond, then, other) => visitor.visit_if(cond, then, other),
+        Node::For(var, seq, body) => visitor.visit_for(var, seq, body),
+        Node::List(items) => visitor.visit_list(items),
+        Node::Index(node, index) => visitor.visit_index(node, index),
+        Node::Lambda(params, body) => visitor.visit_lambda(params, body),
+    }
+}
+
+pub fn walk_unary<V: Visitor + ?Sized>(visitor: &mut V, _op: &UnaryOp, node: &Node) {
+    visitor.visit_node(node)
+}
+
+pub fn walk_binary<V: Visitor + ?Sized>(visitor: &mut V, _op: &BinaryOp, left: &Node, right: &Node) {
+    visitor.visit_node(left);
+    visitor.visit_node(right)
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, _name: &str, args: &[Node]) {
+    args
+        .iter()
+        .for_each(|arg| visitor.visit_node(arg))
+}
+
+pub fn walk_if<V: Visitor + ?Sized>(visitor: &mut V, cond: &Node, then: &Node, other: &Node) {
+    visitor.visit_node(cond);
+    visitor.visit_node(then);
+    visitor.visit_node(other)
+}
+
+pub fn walk_for<V: Visitor + ?Sized>(visitor: &mut V, _var: &str, seq: &Node, body: &Node) {
+    visitor.visit_node(seq);
+    visitor.visit_node(body)
+}
+
+pub fn walk_list<V: Visitor + ?Sized>(visitor: &mut V, items: &[Node]) {
+    items
+        .iter()
+        .for_each(|item| visitor.visit_node(item))
+}
+
+pub fn walk_index<V: Visitor + ?Sized>(visitor: &mut V, node: &Node, index: &Node) {
+    visitor.visit_node(node);
+    visitor.visit_node(index)
+}
+
+pub fn walk_lambda<V: Visitor + ?Sized>(visitor: &mut V, _params: &[String], body: &Node) {
+    visitor.visit_node(body)
+}
+
+/// Collects free variable names in order of first appearance, leaving out
+/// loop variables and lambda parameters where they are bound.
+pub struct VarCollector {
+    pub vars: Vec<String>,
+    bound: Vec<String>
+}
+
+impl Visitor for VarCollector {
+    fn visit_var(&mut self, name: &str) {
+        if !self.vars.iter().chain(&self.bound).any(|var| var == name) {
+            self.vars.push(name.to_string())
+        }
+    }
+    fn visit_for(&mut self, var: &str, seq: &Node, body: &Node) {
+        self.visit_node(seq);
+        self.bound.push(var.to_string());
+        self.visit_node(body);
+        self.bound.pop();
+    }
+    fn visit_lambda(&mut self, params: &[String], body: &Node) {
+        let len = self.bound.len();
+        self.bound.extend(params.iter().cloned());
+        self.visit_node(body);
+        self.bound.truncate(len);
+    }
+}
+
+impl Node {
+    pub fn vars(&self) -> Vec<String> {
+        let mut collector = VarCollector { vars: Vec::new(), bound: Vec::new() };
+        collector.visit_node(self);
+        collector.vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+
+    #[test]
+    fn collects_free_variables_in_order() {
+        assert_eq!(parse("b * a + f(c, a) - b").vars(), ["b", "a", "c"]);
+        assert!(parse("1 + 2").vars().is_empty());
+        assert_eq!(parse("sum(x + y for x in xs)").vars(), ["xs", "y"]);
+        // `x` is free again outside the loop that binds it
+        assert_eq!(parse("[x for x in 1..3] ++ [x]").vars(), ["x"]);
+        assert_eq!(parse("map(xs, |x, y| x + y + z)").vars(), ["xs", "z"]);
+    }
+
+    #[test]
+    fn default_walk_reaches_every_node() {
+        struct Count(usize);
+        impl Visitor for Count {
+            fn visit_node(&mut self, node: &Node) {
+                self.0 += 1;
+                walk_node(self, node)
+            }
+        }
+        let mut count = Count(0);
+        count.visit_node(&parse("-a + max(b, 2 * c) - [d][0]"));
+        assert_eq!(count.0, 13);
+    }
+}
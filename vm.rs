@@ -0,0 +1,297 @@
+use std::fmt;
+
+use crate::builtin::{self, Builtin};
+use crate::eval::{self, EvalError};
+use crate::node::{Node, Value, UnaryOp, BinaryOp};
+use crate::settings::Mode;
+
+#[derive(Clone)]
+pub enum Op {
+    Const(usize),
+    Load(usize),
+
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+
+    Call(&'static Builtin, usize),
+
+    /// Collect the top `n` values into a list.
+    List(usize),
+    Index,
+
+    Jump(usize),
+    /// Pop a condition and jump if it is false.
+    JumpUnless(usize),
+}
+
+/// Stack code compiled from a `Node`. Variables become parameter slots,
+/// numbered in order of first appearance, so one program can be evaluated
+/// over many rows without rebuilding or cloning the tree. Arithmetic
+/// follows the mode it was compiled for, as the tree walker does.
+#[derive(Clone)]
+pub struct Program {
+    code: Vec<Op>,
+    consts: Vec<Value>,
+    slots: Vec<String>,
+    mode: Mode
+}
+
+/// Value stack kept between runs so repeated evaluation doesn't allocate.
+pub struct Frame {
+    stack: Vec<Value>
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new()
+        }
+    }
+}
+
+impl Program {
+    pub fn compile(node: &Node, mode: Mode) -> Result<Self, String> {
+        let mut prog = Self {
+            code: Vec::new(),
+            consts: Vec::new(),
+            slots: node.vars(),
+            mode
+        };
+        prog.emit(node)?;
+        Ok(prog)
+    }
+    fn emit(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Val(val) => {
+                self.consts.push(val.clone());
+                self.code.push(Op::Const(self.consts.len() - 1))
+            }
+            Node::Var(name) => {
+                let slot = self.slot(name).unwrap();
+                self.code.push(Op::Load(slot))
+            }
+            Node::Unary(op, node) => {
+                self.emit(node)?;
+                self.code.push(Op::Unary(op.clone()))
+            }
+            Node::Binary(op @ (BinaryOp::LogicAnd | BinaryOp::LogicOr), left, right) => {
+                // `a && b` runs as `a ? (b ? 1 : 0) : 0`, `a || b` as
+                // `a ? 1 : (b ? 1 : 0)`
+                let (zero, one) = (Node::Val(Value::Num(0)), Node::Val(Value::Num(1)));
+                let right = Node::If(right.clone(), Box::new(one.clone()), Box::new(zero.clone()));
+                let node = match op {
+                    BinaryOp::LogicAnd => Node::If(left.clone(), Box::new(right), Box::new(zero)),
+                    _ => Node::If(left.clone(), Box::new(one), Box::new(right)),
+                };
+                self.emit(&node)?
+            }
+            Node::Binary(op, left, right) => {
+                self.emit(left)?;
+                self.emit(right)?;
+                self.code.push(Op::Binary(op.clone()))
+            }
+            Node::Call(name, args) => {
+                let func = match builtin::resolve(name, self.mode) {
+                    Some(func) if func.is_higher() => {
+                        return Err(format!("{} is not supported by the VM", name))
+                    }
+                    Some(func) => func,
+                    None => return Err(format!("unknown function {}", name))
+                };
+                if args.len() != func.params.len() {
+                    return Err(format!("{} expect {} arguments, found {}", name, func.params.len(), args.len()))
+                }
+                for arg in args {
+                    self.emit(arg)?;
+                }
+                self.code.push(Op::Call(func, args.len()))
+            }
+            Node::If(cond, then, other) => {
+                self.emit(cond)?;
+                let jump_other = self.code.len();
+                self.code.push(Op::JumpUnless(0));
+                self.emit(then)?;
+                let jump_end = self.code.len();
+                self.code.push(Op::Jump(0));
+                self.code[jump_other] = Op::JumpUnless(self.code.len());
+                self.emit(other)?;
+                self.code[jump_end] = Op::Jump(self.code.len())
+            }
+            Node::For(..) => return Err(format!("for is not supported by the VM")),
+            Node::Lambda(..) => return Err(format!("lambda is not supported by the VM")),
+            Node::List(items) => {
+                for item in items {
+                    self.emit(item)?;
+                }
+                self.code.push(Op::List(items.len()))
+            }
+            Node::Index(node, index) => {
+                self.emit(node)?;
+                self.emit(index)?;
+                self.code.push(Op::Index)
+            }
+        }
+        Ok(())
+    }
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot == name)
+    }
+    /// Run the program with `params[i]` bound to `slots()[i]`.
+    pub fn eval(&self, frame: &mut Frame, params: &[Value]) -> Result<Value, EvalError> {
+        if params.len() != self.slots.len() {
+            return Err(EvalError::Arity {
+                name: format!("program"),
+                expect: self.slots.len(),
+                found: params.len()
+            })
+        }
+        let stack = &mut frame.stack;
+        stack.clear();
+        let mut pc = 0;
+        while let Some(op) = self.code.get(pc) {
+            pc += 1;
+            match op {
+                Op::Const(index) => stack.push(self.consts[*index].clone()),
+                Op::Load(slot) => stack.push(params[*slot].clone()),
+
+                Op::Unary(op) => {
+                    let val = stack.pop().unwrap();
+                    stack.push(op.apply(val)?)
+                }
+                Op::Binary(op) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    stack.push(eval::binary(self.mode, op, left, right)?)
+                }
+
+                Op::Call(func, argc) => {
+                    let base = stack.len() - argc;
+                    let res = func.call(&stack[base..], None)?;
+                    stack.truncate(base);
+                    stack.push(res)
+                }
+
+                Op::List(len) => {
+                    let items = stack.split_off(stack.len() - len);
+                    stack.push(Value::list(items))
+                }
+                Op::Index => {
+                    let index = stack.pop().unwrap();
+                    let val = stack.pop().unwrap();
+                    stack.push(val.index(&index)?)
+                }
+
+                Op::Jump(target) => pc = *target,
+                Op::JumpUnless(target) => {
+                    if !stack.pop().unwrap().truthy()? {
+                        pc = *target
+                    }
+                }
+            }
+        }
+        Ok(stack.pop().unwrap())
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Const(index) => write!(f, "const {}", index),
+            Op::Load(slot) => write!(f, "load {}", slot),
+
+            Op::Unary(op) => write!(f, "unary {}", op),
+            Op::Binary(op) => write!(f, "binary {}", op),
+
+            Op::Call(func, argc) => write!(f, "call {} {}", func.name, argc),
+
+            Op::List(len) => write!(f, "list {}", len),
+            Op::Index => write!(f, "index"),
+
+            Op::Jump(target) => write!(f, "jump {}", target),
+            Op::JumpUnless(target) => write!(f, "jump_unless {}", target),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, val) in self.consts.iter().enumerate() {
+            writeln!(f, "const {} = {}", i, val)?;
+        }
+        for (i, name) in self.slots.iter().enumerate() {
+            writeln!(f, "slot {} = {}", i, name)?;
+        }
+        for op in &self.code {
+            writeln!(f, "  {}", op)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench;
+    use crate::parser::tests::parse;
+
+    fn run(src: &str, params: &[Value]) -> String {
+        let prog = Program::compile(&parse(src), Mode::Exact).unwrap();
+        match prog.eval(&mut Frame::new(), params) {
+            Ok(val) => val.to_string(),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn binds_slots_in_order_of_first_use() {
+        let prog = Program::compile(&parse("y - x * y"), Mode::Exact).unwrap();
+        assert_eq!(prog.slots(), ["y", "x"]);
+        assert_eq!(run("y - x * y", &[Value::Num(3), Value::Num(2)]), "-3");
+    }
+
+    #[test]
+    fn calls_builtins() {
+        assert_eq!(run("max(x, 2) + abs(-x)", &[Value::Num(-4)]), "6");
+        assert_eq!(run("gcd(12, 18)", &[]), "6");
+        assert_eq!(run("[x, 2 * x][1]", &[Value::Num(-4)]), "-8");
+    }
+
+    #[test]
+    fn rejects_calls_it_cannot_make() {
+        assert_eq!(Program::compile(&parse("nope(1)"), Mode::Exact).err().unwrap(), "unknown function nope");
+        assert_eq!(Program::compile(&parse("max(1)"), Mode::Exact).err().unwrap(), "max expect 2 arguments, found 1");
+        assert_eq!(Program::compile(&parse("sum(x for x in 1..3)"), Mode::Exact).err().unwrap(), "for is not supported by the VM");
+        assert_eq!(Program::compile(&parse("map(xs, |x| x)"), Mode::Exact).err().unwrap(), "map is not supported by the VM");
+    }
+
+    #[test]
+    fn follows_the_mode() {
+        for (mode, res) in [(Mode::Exact, "3"), (Mode::Float, "3.5")] {
+            let prog = Program::compile(&parse("x / 2"), mode).unwrap();
+            assert_eq!(prog.eval(&mut Frame::new(), &[Value::Num(7)]).ok().unwrap().to_string(), res);
+            assert_eq!(bench::compare(&parse("x / 2 + y"), 50, mode).unwrap().rows, 50);
+        }
+        for src in ["sqrt(x)", "x ** 0.5"] {
+            let prog = Program::compile(&parse(src), Mode::Complex).unwrap();
+            assert_eq!(prog.eval(&mut Frame::new(), &[Value::Num(-4)]).ok().unwrap().to_string(), "2i");
+            assert_eq!(bench::compare(&parse(src), 50, Mode::Complex).unwrap().rows, 50);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(run("x + y", &[Value::Num(1)]), "error: program expect 2 arguments, found 1");
+        assert_eq!(run("x / 0", &[Value::Num(1)]), "error: division by zero");
+    }
+
+    #[test]
+    fn bench_agrees_with_the_tree_walker() {
+        let report = bench::compare(&parse("x * y % 7 + max(x - 50, y)"), 200, Mode::Exact).unwrap();
+        assert_eq!(report.rows, 200);
+    }
+}